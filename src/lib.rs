@@ -0,0 +1,42 @@
+//! forwards output to telegram chats via a bot.
+//!
+//! besides the `teleecho` command, the processor that splits text into
+//! messages and sends them with respect to the rate limits of telegram can be
+//! used from other programs:
+//!
+//! ```no_run
+//! use std::io::Write;
+//!
+//! let mut processor = teleecho::TeleechoProcessor::builder("123456:ABC-DEF1234ghIkl")
+//!     .chat(12345678)
+//!     .build()
+//!     .unwrap();
+//!
+//! writeln!(processor, "backup done").unwrap();
+//!
+//! let report = processor.close();
+//! if !report.is_ok() {
+//!     println!("{} messages could not be sent", report.failed);
+//! }
+//! ```
+//!
+//! connections can be taken from the config file of the command with
+//! `Config::load` and `Config::find`, see `TeleechoBuilder::from_connection`.
+
+#![recursion_limit = "1024"]
+#[macro_use]
+extern crate error_chain;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+
+pub mod teleecho;
+
+pub use teleecho::{botapi, config, envelope, error, listener, logger};
+pub use teleecho::config::{Config, Connection};
+pub use teleecho::error::{Error, ErrorKind, Result};
+pub use teleecho::logger::{TeleechoLogger, TeleechoLoggerHandle};
+pub use teleecho::teleecho::{ChatMigration, DeliveryFailure, DeliveryReport, Recipient,
+                             SendOptions, TeleechoBuilder, TeleechoProcessor};
+#[cfg(feature = "tracing-layer")]
+pub use teleecho::tracing_layer::{TeleechoLayer, TeleechoLayerHandle};
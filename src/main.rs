@@ -1,48 +1,111 @@
-#![feature(io)]
-#![recursion_limit = "1024"]
-#[macro_use]
-extern crate error_chain;
+extern crate teleecho;
 extern crate clap;
-extern crate rand;
+extern crate ctrlc;
 
 use clap::{Arg, App, SubCommand, AppSettings};
-mod teleecho;
 use teleecho::error::*;
-use teleecho::teleecho::TeleechoProcessor;
-use teleecho::config::Config;
+use teleecho::teleecho::{ask, doctor, pairing, read, run};
+use teleecho::teleecho::{Recipient, SendOptions, TeleechoProcessor};
+use teleecho::botapi::BotApi;
+use teleecho::config::{Config, Connection, ChatTarget};
 use std::fs::OpenOptions;
+use std::sync::atomic::{ATOMIC_BOOL_INIT, AtomicBool, Ordering};
 
-macro_rules! unwrap_or_return {
+macro_rules! unwrap_or_exit {
     ($expr:expr,$message:expr) => (
         match $expr {
             Ok(r) => r,
             Err(e) => {
-                println!("error {}: {}",$message,e);
-                return
+                let e = Error::from(e);
+                exit_with_error(&format!("error {}: {}",$message,e), e.exit_code())
             }
         }
+    );
+    ($expr:expr,$message:expr,$code:expr) => (
+        match $expr {
+            Ok(r) => r,
+            Err(e) => exit_with_error(&format!("error {}: {}",$message,e), $code)
+        }
     )
 }
 
-macro_rules! print_err {
+macro_rules! exit_on_err {
     ($expr:expr) => (
         match $expr {
             Ok(_) => {},
-            Err(e) => println!("error: {}",e)
+            Err(e) => exit_with_error(&format!("error: {}",e), e.exit_code())
         }
     )
 }
 
+/// set while pairing waits for the user, which is the only time Ctrl-C cancels;
+/// once pairing finished, the connection is saved without interruption
+static PAIRING: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// how often to check whether all chats failed while waiting for input
+const FAILURE_POLL_MS: u64 = 200;
+
+// writes the given message to stderr and exits with the given status
+fn exit_with_error(message: &str, code: i32) -> ! {
+    use std::io::Write;
+
+    let _ = writeln!(std::io::stderr(), "{}", message);
+    std::process::exit(code);
+}
+
 fn process_input(telelog_bot: &mut TeleechoProcessor) {
     use std::io;
-    use std::io::Read;
+    use std::io::{Read, Write};
+    use std::sync::mpsc::{self, RecvTimeoutError};
+    use std::thread;
+    use std::time::Duration;
+
+    // stdin is read by its own thread, so it is noticed that all chats failed
+    // even while no input arrives
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        let mut stdin = stdin.lock();
+        let mut buffer = [0; 4096];
+        loop {
+            let bytes = match stdin.read(&mut buffer) {
+                Ok(0) => return,
+                Ok(length) => buffer[..length].to_vec(),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => return,
+            };
+            if sender.send(bytes).is_err() {
+                return;
+            }
+        }
+    });
+
+    loop {
+        match receiver.recv_timeout(Duration::from_millis(FAILURE_POLL_MS)) {
+            // chars split across reads are joined by the processor
+            Ok(bytes) => {
+                let _ = telelog_bot.write_all(&bytes);
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
 
-    for elem in io::stdin().chars() {
-        let c = elem.unwrap_or(' ');
-        telelog_bot.append_to_input_buffer(c);
+        // nothing can be sent anymore, so stop reading
+        if telelog_bot.has_failed() {
+            break;
+        }
     }
 }
 
+// opens the config file or creates it if not existent;
+// a newly created file is only readable by the user, as it will contain tokens
+fn open_config_file(path: &std::path::Path) -> std::io::Result<std::fs::File> {
+    let mut options = OpenOptions::new();
+    options.read(true).write(true).create(true).append(false);
+    teleecho::config::restrict_permissions(&mut options);
+    options.open(path)
+}
+
 // create the clap app and return the matches
 fn create_clap_app<'a, 'b>() -> clap::ArgMatches<'a>
     where 'a: 'b
@@ -57,6 +120,14 @@ fn create_clap_app<'a, 'b>() -> clap::ArgMatches<'a>
                  .help("name of the connection to use for sending")
                  .required(false)
                  .takes_value(true))
+        .arg(Arg::with_name("topic")
+                 .short("t")
+                 .long("topic")
+                 .value_name("ID|NAME")
+                 .help("forum topic of the group to send to, by thread id or by a name set with \
+                        `set <connection> topic:<NAME> <ID>`")
+                 .required(false)
+                 .takes_value(true))
         .arg(Arg::with_name("config")
                  .short("c")
                  .long("config")
@@ -64,18 +135,62 @@ fn create_clap_app<'a, 'b>() -> clap::ArgMatches<'a>
                  .help("path to config file; defaults to ~/.teleecho.conf")
                  .required(false)
                  .takes_value(true))
+        .arg(Arg::with_name("quiet")
+                 .short("q")
+                 .long("quiet")
+                 .help("suppresses warnings; errors are still written to stderr"))
         .subcommand(SubCommand::with_name("new")
                         .about("registers bot to user connection")
                         .setting(AppSettings::ColoredHelp)
                         .arg(Arg::with_name("token")
-                                 .help("token from botfather to send from")
-                                 .required(true))
+                                 .help("token from botfather to send from; may also be an \
+                                        environment reference like ${BOT_TOKEN}")
+                                 .required_unless("token-command"))
                         .arg(Arg::with_name("name")
                                  .takes_value(true)
                                  .help("name to specify this connection")
+                                 .required(true))
+                        .arg(Arg::with_name("token-command")
+                                 .long("token-command")
+                                 .value_name("COMMAND")
+                                 .help("shell command printing the token, e.g. \"pass show \
+                                        bots/backup\"; stored instead of the token")
+                                 .takes_value(true))
+                        .args(&pairing_args())
+                        .arg(Arg::with_name("json")
+                                 .long("json")
+                                 .help("prints the created connection as json")))
+        .subcommand(SubCommand::with_name("add-chat")
+                        .about("pairs another chat that receives the messages of a connection")
+                        .setting(AppSettings::ColoredHelp)
+                        .arg(Arg::with_name("name")
+                                 .help("name of the connection")
+                                 .required(true))
+                        .args(&pairing_args()))
+        .subcommand(SubCommand::with_name("remove-chat")
+                        .about("stops sending the messages of a connection to a chat")
+                        .setting(AppSettings::ColoredHelp)
+                        .arg(Arg::with_name("name")
+                                 .help("name of the connection")
+                                 .required(true))
+                        .arg(Arg::with_name("chat id")
+                                 .help("id of the chat to remove")
                                  .required(true)))
         .subcommand(SubCommand::with_name("list")
                         .about("list all connections")
+                        .arg(Arg::with_name("long")
+                                 .short("l")
+                                 .long("long")
+                                 .help("also shows bot, chat and options of the connections"))
+                        .arg(Arg::with_name("json")
+                                 .long("json")
+                                 .conflicts_with("long")
+                                 .help("prints the connections as json"))
+                        .arg(Arg::with_name("fetch")
+                                 .long("fetch")
+                                 .help("with --long or --json, asks telegram for bot and chat \
+                                        information that is not cached yet; this resolves \
+                                        the tokens and may run token commands"))
                         .setting(AppSettings::ColoredHelp))
         .subcommand(SubCommand::with_name("remove")
                         .about("removes a connection")
@@ -84,101 +199,1125 @@ fn create_clap_app<'a, 'b>() -> clap::ArgMatches<'a>
                                  .takes_value(true)
                                  .required(true))
                         .setting(AppSettings::ColoredHelp))
+        .subcommand(SubCommand::with_name("rename")
+                        .about("renames a connection")
+                        .arg(Arg::with_name("name")
+                                 .help("current name of the connection")
+                                 .required(true))
+                        .arg(Arg::with_name("new name")
+                                 .help("new name of the connection")
+                                 .required(true))
+                        .setting(AppSettings::ColoredHelp))
+        .subcommand(SubCommand::with_name("copy")
+                        .about("copies a connection with all its options")
+                        .arg(Arg::with_name("name")
+                                 .help("name of the connection to copy")
+                                 .required(true))
+                        .arg(Arg::with_name("new name")
+                                 .help("name of the copy")
+                                 .required(true))
+                        .setting(AppSettings::ColoredHelp))
+        .subcommand(SubCommand::with_name("show")
+                        .about("shows the settings of a connection; the token is masked")
+                        .arg(Arg::with_name("name")
+                                 .help("name of the connection; the default if not given")
+                                 .required(false))
+                        .setting(AppSettings::ColoredHelp))
+        .subcommand(SubCommand::with_name("set")
+                        .about("changes an option of a connection; `none` unsets it")
+                        .arg(Arg::with_name("name")
+                                 .help("name of the connection")
+                                 .required(true))
+                        .arg(Arg::with_name("key")
+                                 .help("the option to change: token, token_command, chat_id, \
+                                        chat_ids, thread_id, topic:<NAME>, parse_mode or \
+                                        disable_link_preview")
+                                 .validator(validate_connection_key)
+                                 .required(true))
+                        .arg(Arg::with_name("value")
+                                 .help("the new value")
+                                 .required(true))
+                        .setting(AppSettings::ColoredHelp))
+        .subcommand(SubCommand::with_name("export")
+                        .about("writes connections to stdout or a file to import them on \
+                                another machine")
+                        .arg(Arg::with_name("names")
+                                 .help("connections to export; all if none given")
+                                 .multiple(true)
+                                 .required(false))
+                        .arg(Arg::with_name("output")
+                                 .short("o")
+                                 .long("output")
+                                 .value_name("FILE")
+                                 .help("file to write to instead of stdout")
+                                 .takes_value(true))
+                        .arg(Arg::with_name("encrypt")
+                                 .long("encrypt")
+                                 .help("encrypts the export with a passphrase; read from \
+                                        TELEECHO_PASSPHRASE if set"))
+                        .setting(AppSettings::ColoredHelp))
+        .subcommand(SubCommand::with_name("import")
+                        .about("adds connections written by export")
+                        .arg(Arg::with_name("file")
+                                 .help("file to read from; stdin if not given")
+                                 .required(false))
+                        .arg(Arg::with_name("rename")
+                                 .long("rename")
+                                 .conflicts_with("overwrite")
+                                 .help("imports connections with a taken name under a new name"))
+                        .arg(Arg::with_name("overwrite")
+                                 .long("overwrite")
+                                 .help("replaces existing connections with the same name"))
+                        .setting(AppSettings::ColoredHelp))
+        .subcommand(SubCommand::with_name("doctor")
+                        .about("checks the config file and connections and sends a silent \
+                                test message")
+                        .arg(Arg::with_name("name")
+                                 .help("connection to check; all if not given")
+                                 .required(false))
+                        .setting(AppSettings::ColoredHelp))
+        .subcommand(SubCommand::with_name("run")
+                        .about("runs a command and sends its output; text replied in the chats \
+                                is written into its input")
+                        .setting(AppSettings::TrailingVarArg)
+                        .arg(Arg::with_name("name")
+                                 .short("n")
+                                 .long("name")
+                                 .value_name("CONNECTION NAME")
+                                 .help("connection to use; the default if not given")
+                                 .takes_value(true))
+                        .arg(Arg::with_name("command")
+                                 .help("the command to run with its arguments")
+                                 .multiple(true)
+                                 .required(true))
+                        .setting(AppSettings::ColoredHelp))
+        .subcommand(SubCommand::with_name("send")
+                        .about("sends the given text as one message and prints its id")
+                        .arg(Arg::with_name("text")
+                                 .help("the text to send; several arguments are joined by spaces")
+                                 .multiple(true)
+                                 .required(true))
+                        .args(&one_shot_args())
+                        .setting(AppSettings::ColoredHelp))
+        .subcommand(SubCommand::with_name("file")
+                        .about("uploads files and prints the ids of the messages; several files \
+                                are sent as album")
+                        .arg(Arg::with_name("path")
+                                 .help("the files to upload")
+                                 .multiple(true)
+                                 .required(true))
+                        .arg(Arg::with_name("caption")
+                                 .long("caption")
+                                 .value_name("TEXT")
+                                 .help("text shown below the file, or the first file of an album")
+                                 .takes_value(true))
+                        .arg(Arg::with_name("photo")
+                                 .long("photo")
+                                 .help("sends the files as photos, which are shown inline, \
+                                        instead of documents"))
+                        .args(&one_shot_args())
+                        .setting(AppSettings::ColoredHelp))
+        .subcommand(SubCommand::with_name("ask")
+                        .about("asks a question with buttons and prints the chosen answer; \
+                                exits with the index of the choice, 124 on timeout")
+                        .arg(Arg::with_name("question")
+                                 .help("the question to ask")
+                                 .required(true))
+                        .arg(Arg::with_name("choices")
+                                 .long("choices")
+                                 .value_name("CHOICES")
+                                 .help("comma separated answers, one button each; \
+                                        at most 100, as the index is the exit status")
+                                 .validator(validate_choices)
+                                 .default_value("yes,no")
+                                 .takes_value(true))
+                        .arg(Arg::with_name("timeout")
+                                 .long("timeout")
+                                 .value_name("DURATION")
+                                 .help("how long to wait for an answer, e.g. 30s, 10m or 2h; \
+                                        forever if not given")
+                                 .takes_value(true))
+                        .arg(Arg::with_name("name")
+                                 .short("n")
+                                 .long("name")
+                                 .value_name("CONNECTION NAME")
+                                 .help("connection to use; the default if not given")
+                                 .takes_value(true))
+                        .setting(AppSettings::ColoredHelp))
+        .subcommand(SubCommand::with_name("read")
+                        .about("waits for the next message from the chat and prints it; \
+                                files are downloaded and their path printed")
+                        .arg(Arg::with_name("timeout")
+                                 .long("timeout")
+                                 .value_name("DURATION")
+                                 .help("how long to wait for a message, e.g. 30s, 10m or 2h; \
+                                        forever if not given; exits with 124 then")
+                                 .takes_value(true))
+                        .arg(Arg::with_name("dir")
+                                 .long("dir")
+                                 .value_name("DIR")
+                                 .help("directory to download documents and photos to")
+                                 .default_value(".")
+                                 .takes_value(true))
+                        .arg(Arg::with_name("name")
+                                 .short("n")
+                                 .long("name")
+                                 .value_name("CONNECTION NAME")
+                                 .help("connection to use; the default if not given")
+                                 .takes_value(true))
+                        .setting(AppSettings::ColoredHelp))
+        .subcommand(SubCommand::with_name("default")
+                        .about("sets the connection used when none is given")
+                        .arg(Arg::with_name("name")
+                                 .help("name of the connection")
+                                 .takes_value(true)
+                                 .required(true))
+                        .setting(AppSettings::ColoredHelp))
+        .subcommand(SubCommand::with_name("config")
+                        .about("manages the config file itself")
+                        .setting(AppSettings::ColoredHelp)
+                        .setting(AppSettings::SubcommandRequiredElseHelp)
+                        .subcommand(SubCommand::with_name("encrypt")
+                                        .about("encrypts the config file with a passphrase; \
+                                                read from TELEECHO_PASSPHRASE if set")
+                                        .setting(AppSettings::ColoredHelp))
+                        .subcommand(SubCommand::with_name("decrypt")
+                                        .about("stores the config file as plain text again")
+                                        .setting(AppSettings::ColoredHelp)))
         .get_matches()
 }
 
+/// the choices must fit below the exit statuses of a timeout or failure
+fn validate_choices(choices: String) -> std::result::Result<(), String> {
+    let count = choices.split(',').filter(|c| !c.trim().is_empty()).count();
+    if count > ask::MAX_CHOICES {
+        Err(format!("at most {} choices are possible", ask::MAX_CHOICES))
+    } else {
+        Ok(())
+    }
+}
+
+// asks the question and prints the choice; returns the exit status
+fn subcommand_ask(matches: &clap::ArgMatches,
+                  config: &Config,
+                  connection: Option<&str>,
+                  topic: Option<&str>)
+                  -> Result<i32> {
+    let c = try!(config.find(matches.value_of("name").or(connection)));
+    let token = try!(c.resolve_token());
+    let topic = match topic {
+        Some(t) => Some(try!(c.resolve_topic(t))),
+        None => None,
+    };
+
+    let question = matches.value_of("question").unwrap();
+    let choices = matches.value_of("choices")
+        .unwrap()
+        .split(',')
+        .map(|c| c.trim())
+        .filter(|c| !c.is_empty())
+        .collect::<Vec<&str>>();
+    let timeout = match matches.value_of("timeout") {
+        Some(t) => Some(try!(ask::parse_duration(t))),
+        None => None,
+    };
+
+    match try!(ask::ask(&token,
+                        &c.chats,
+                        &c.recipients(topic),
+                        question,
+                        &choices,
+                        timeout)) {
+        Some(choice) => {
+            println!("{}", choices[choice]);
+            Ok(choice as i32)
+        }
+        None => Ok(ask::EXIT_TIMEOUT),
+    }
+}
+
+// waits for a message and prints it; returns the exit status
+fn subcommand_read(matches: &clap::ArgMatches,
+                   config: &Config,
+                   connection: Option<&str>)
+                   -> Result<i32> {
+    let (token, chats) = try!(config.get(matches.value_of("name").or(connection)));
+
+    let timeout = match matches.value_of("timeout") {
+        Some(t) => Some(try!(ask::parse_duration(t))),
+        None => None,
+    };
+    let dir = std::path::Path::new(matches.value_of("dir").unwrap());
+
+    match try!(read::read(&token, &chats, timeout, dir)) {
+        Some(text) => {
+            println!("{}", text);
+            Ok(0)
+        }
+        None => Ok(ask::EXIT_TIMEOUT),
+    }
+}
+
 fn subcommand_remove(matches: &clap::ArgMatches,
                      mut config: &mut Config,
-                     mut f: &mut std::fs::File)
+                     path: &std::path::Path)
                      -> Result<()> {
     let to_remove = matches.value_of("name").unwrap();
 
     try!(config.remove(&to_remove));
-    try!(config.save_to(&mut f));
+    try!(config.save(path));
+
+    Ok(())
+}
+
+fn subcommand_default(matches: &clap::ArgMatches,
+                      mut config: &mut Config,
+                      path: &std::path::Path)
+                      -> Result<()> {
+    let name = matches.value_of("name").unwrap();
+
+    try!(config.set_default(&name));
+    try!(config.save(path));
+
+    println!("default connection is now: {}", name);
+    Ok(())
+}
+
+fn subcommand_config(matches: &clap::ArgMatches,
+                     mut config: &mut Config,
+                     path: &std::path::Path)
+                     -> Result<()> {
+    if let Some(_) = matches.subcommand_matches("encrypt") {
+        if config.is_encrypted() {
+            return Err(ErrorKind::ConfigAlreadyEncrypted.into());
+        }
+        let passphrase = try!(teleecho::config::read_passphrase(true));
+        config.set_passphrase(Some(passphrase));
+        try!(config.save(path));
+        println!("config file encrypted");
+    } else if let Some(_) = matches.subcommand_matches("decrypt") {
+        if !config.is_encrypted() {
+            return Err(ErrorKind::ConfigNotEncrypted.into());
+        }
+        config.set_passphrase(None);
+        try!(config.save(path));
+        println!("config file decrypted");
+    }
+
+    Ok(())
+}
+
+// checks a name for a new connection;
+// whitespace is not allowed and replaced by dashes, and the name must not be taken
+fn new_connection_name(config: &Config, name: &str) -> Result<String> {
+    let name_without_whitespace = try!(teleecho::config::connection_name(name));
+
+    if config.contains(&name_without_whitespace) {
+        return Err(ErrorKind::ConfigConnectionExists(name_without_whitespace).into());
+    }
+
+    Ok(name_without_whitespace)
+}
+
+fn subcommand_rename(matches: &clap::ArgMatches,
+                     mut config: &mut Config,
+                     path: &std::path::Path)
+                     -> Result<()> {
+    let from = matches.value_of("name").unwrap();
+    let to = try!(new_connection_name(config, matches.value_of("new name").unwrap()));
+
+    try!(config.rename(from, &to));
+    try!(config.save(path));
+
+    println!("renamed {} to {}", from, to);
+    Ok(())
+}
+
+fn subcommand_copy(matches: &clap::ArgMatches,
+                   mut config: &mut Config,
+                   path: &std::path::Path)
+                   -> Result<()> {
+    let from = matches.value_of("name").unwrap();
+    let to = try!(new_connection_name(config, matches.value_of("new name").unwrap()));
+
+    try!(config.copy(from, &to));
+    try!(config.save(path));
+
+    println!("copied {} to {}", from, to);
+    Ok(())
+}
+
+/// the possible values of `set`'s key, which can't be a fixed list because of the topic names
+fn validate_connection_key(key: String) -> std::result::Result<(), String> {
+    if teleecho::config::is_connection_key(&key) {
+        Ok(())
+    } else {
+        Err(format!("'{}' isn't a valid value\n\t[values: {}]",
+                    key,
+                    teleecho::config::CONNECTION_KEYS.join(", ")))
+    }
+}
+
+fn subcommand_set(matches: &clap::ArgMatches,
+                  mut config: &mut Config,
+                  path: &std::path::Path)
+                  -> Result<()> {
+    // all are required, thus must be Some(...)
+    let name = matches.value_of("name").unwrap();
+    let key = matches.value_of("key").unwrap();
+    let value = matches.value_of("value").unwrap();
+
+    try!(config.set(name, key, value));
+    try!(config.save(path));
+
+    Ok(())
+}
+
+fn subcommand_export(matches: &clap::ArgMatches, config: &Config) -> Result<()> {
+    use std::io::Write;
+
+    let names = matches.values_of("names").map(|v| v.collect()).unwrap_or(vec![]);
+
+    let passphrase = if matches.is_present("encrypt") {
+        Some(try!(teleecho::config::read_passphrase(true)))
+    } else {
+        None
+    };
+
+    let exported = try!(config.export(&names, passphrase.as_ref().map(|p| p.as_str())));
+
+    match matches.value_of("output") {
+        Some(path) => {
+            let mut options = OpenOptions::new();
+            options.write(true).create(true).truncate(true);
+            teleecho::config::restrict_permissions(&mut options);
+            let mut out = try!(options.open(path));
+            try!(out.write_all(exported.as_bytes()));
+        }
+        None => {
+            let stdout = std::io::stdout();
+            let mut out = stdout.lock();
+            try!(out.write_all(exported.as_bytes()));
+            try!(out.write_all(b"\n"));
+        }
+    }
+
+    Ok(())
+}
+
+/// what to do with an imported connection whose name is already taken
+enum ImportConflict {
+    Overwrite,
+    Rename(String),
+    Skip,
+}
+
+// asks the user what to do with the given conflicting connection
+fn ask_import_conflict(config: &Config, name: &str) -> Result<ImportConflict> {
+    use std::io::{BufRead, Write};
+
+    let stdin = std::io::stdin();
+    loop {
+        try!(write!(std::io::stderr(),
+                    "connection {} already exists: [o]verwrite, [r]ename or [s]kip? ",
+                    name));
+        try!(std::io::stderr().flush());
+
+        let mut answer = String::new();
+        try!(stdin.lock().read_line(&mut answer));
+
+        match answer.trim() {
+            "o" => return Ok(ImportConflict::Overwrite),
+            "s" => return Ok(ImportConflict::Skip),
+            "r" => {
+                try!(write!(std::io::stderr(), "new name: "));
+                try!(std::io::stderr().flush());
+
+                let mut new_name = String::new();
+                try!(stdin.lock().read_line(&mut new_name));
+
+                match new_connection_name(config, &new_name) {
+                    Ok(n) => return Ok(ImportConflict::Rename(n)),
+                    Err(e) => try!(writeln!(std::io::stderr(), "error: {}", e)),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// finds a free name for a conflicting connection by appending a number
+fn free_connection_name(config: &Config, name: &str) -> String {
+    let mut i = 2;
+    loop {
+        let candidate = format!("{}-{}", name, i);
+        if !config.contains(&candidate) {
+            return candidate;
+        }
+        i += 1;
+    }
+}
+
+fn subcommand_import(matches: &clap::ArgMatches,
+                     mut config: &mut Config,
+                     path: &std::path::Path)
+                     -> Result<()> {
+    use std::io::Read;
+
+    let mut content = String::new();
+    let from_stdin = match matches.value_of("file") {
+        Some(path) => {
+            try!(try!(std::fs::File::open(path)).read_to_string(&mut content));
+            false
+        }
+        None => {
+            try!(std::io::stdin().read_to_string(&mut content));
+            true
+        }
+    };
+
+    // stdin is used up by the import, so the passphrase can not be prompted for
+    let connections = try!(Config::parse_export(&content, !from_stdin));
+
+    for mut connection in connections {
+        connection.name = try!(teleecho::config::connection_name(&connection.name));
+        let name = connection.name.clone();
+
+        if config.contains(&name) {
+            let resolution = if matches.is_present("overwrite") {
+                ImportConflict::Overwrite
+            } else if matches.is_present("rename") {
+                ImportConflict::Rename(free_connection_name(config, &name))
+            } else if from_stdin {
+                // stdin is already used up by the import, so it cannot be asked
+                return Err(ErrorKind::ConfigConnectionExists(name).into());
+            } else {
+                try!(ask_import_conflict(config, &name))
+            };
+
+            match resolution {
+                ImportConflict::Overwrite => {
+                    config.replace_entry(connection);
+                    println!("overwritten: {}", name);
+                }
+                ImportConflict::Rename(new_name) => {
+                    connection.name = new_name.clone();
+                    try!(config.add_entry(connection));
+                    println!("imported: {} as {}", name, new_name);
+                }
+                ImportConflict::Skip => println!("skipped: {}", name),
+            }
+        } else {
+            try!(config.add_entry(connection));
+            println!("imported: {}", name);
+        }
+    }
+
+    try!(config.save(path));
+    Ok(())
+}
+
+// asks telegram for the bot and chat of every connection
+// where this is not cached yet; returns whether anything was fetched
+fn fill_connection_info(config: &mut Config) -> bool {
+    use teleecho::botapi::BotApi;
+    use teleecho::config::BotInfo;
+
+    let mut changed = false;
+    let missing = config.connections()
+        .iter()
+        .filter(|c| c.bot.is_none() || c.chats.iter().any(|chat| chat.chat_type.is_none()))
+        .cloned()
+        .collect::<Vec<Connection>>();
+
+    for c in missing {
+        // if the token can not be resolved right now the information stays unknown
+        let api = match c.resolve_token() {
+            Ok(token) => BotApi::new(&token),
+            Err(_) => continue,
+        };
+
+        let bot = api.get_me().ok().and_then(|me| {
+            match (me.find("id").and_then(|i| i.as_i64()),
+                   me.find("username").and_then(|u| u.as_str())) {
+                (Some(id), Some(username)) => {
+                    Some(BotInfo {
+                        id: id,
+                        username: String::from(username),
+                    })
+                }
+                _ => None,
+            }
+        });
+        let chat_types = c.chats
+            .iter()
+            .filter(|chat| chat.chat_type.is_none())
+            .filter_map(|chat| {
+                api.get_chat(chat.id)
+                    .ok()
+                    .and_then(|info| info.find("type").and_then(|t| t.as_str()).map(String::from))
+                    .map(|t| (chat.id, t))
+            })
+            .collect::<Vec<(i64, String)>>();
+
+        if bot.is_some() || chat_types.len() > 0 {
+            changed = true;
+            let _ = config.set_cached_info(&c.name, bot, chat_types);
+        }
+    }
+
+    changed
+}
+
+fn subcommand_list(matches: &clap::ArgMatches,
+                   mut config: &mut Config,
+                   path: &std::path::Path)
+                   -> Result<()> {
+    if !matches.is_present("long") && !matches.is_present("json") {
+        config.list();
+        return Ok(());
+    }
+
+    // the bot and chat information is cached in the config file;
+    // fetching it resolves tokens and needs the network, so only when asked
+    if matches.is_present("fetch") && fill_connection_info(config) {
+        try!(config.save(path));
+    }
+
+    if matches.is_present("json") {
+        println!("{}", try!(config.list_json()));
+    } else {
+        config.list_long();
+    }
 
     Ok(())
 }
 
 fn subcommand_new(matches: &clap::ArgMatches,
                   mut config: &mut Config,
-                  mut f: &mut std::fs::File)
+                  path: &std::path::Path)
                   -> Result<()> {
     // is required, thus must be Some(...)
-    let token = matches.value_of("token").unwrap();
     let name = matches.value_of("name").unwrap();
+    let name_without_whitespace = try!(new_connection_name(config, name));
+
+    // either a token or a token command is given; this is stored as given,
+    // the resolved token is used for pairing
+    let token_command = matches.value_of("token-command").map(String::from);
+    let token = if token_command.is_some() {
+        None
+    } else {
+        matches.value_of("token").map(String::from)
+    };
+    let mut connection = Connection::new(name_without_whitespace.clone(),
+                                         token,
+                                         token_command,
+                                         vec![]);
+    let token = try!(connection.resolve_token());
+
+    for pairing in try!(pair_chats(matches, &token)) {
+        if let (Some(name), Some(id)) = (pairing.topic_name.clone(), pairing.thread_id) {
+            connection.topics.insert(name, id);
+        }
+        try!(connection.add_chat(chat_target(pairing)));
+    }
+    try!(config.add_entry(connection));
+    try!(config.save(path));
+
+    if matches.is_present("json") {
+        println!("{}", try!(config.connection_json(&name_without_whitespace)));
+    } else {
+        println!("new connection successfully created: {}",
+                 name_without_whitespace);
+    }
+    Ok(())
+}
+
+fn subcommand_add_chat(matches: &clap::ArgMatches,
+                       mut config: &mut Config,
+                       path: &std::path::Path)
+                       -> Result<()> {
+    let name = matches.value_of("name").unwrap();
+    let token = try!(try!(config.find(Some(name))).resolve_token());
+
+    for pairing in try!(pair_chats(matches, &token)) {
+        if let (Some(topic), Some(id)) = (pairing.topic_name.clone(), pairing.thread_id) {
+            try!(config.set(name, &format!("topic:{}", topic), &id.to_string()));
+        }
+        try!(config.add_chat(name, chat_target(pairing)));
+    }
+    try!(config.save(path));
+
+    println!("added chat to connection: {}", name);
+    Ok(())
+}
+
+fn subcommand_remove_chat(matches: &clap::ArgMatches,
+                          mut config: &mut Config,
+                          path: &std::path::Path)
+                          -> Result<()> {
+    let name = matches.value_of("name").unwrap();
+    let id = try!(parse_chat_id(matches.value_of("chat id").unwrap()));
+
+    try!(config.remove_chat(name, id));
+    try!(config.save(path));
+
+    Ok(())
+}
+
+// the chat to store for a pairing, including the topic it was paired in
+fn chat_target(pairing: pairing::Pairing) -> ChatTarget {
+    let mut chat = ChatTarget::new(pairing.chat_id, Some(pairing.chat_type));
+    chat.thread_id = pairing.thread_id;
+    chat.user_id = pairing.user.map(|(_, id)| id);
+    chat
+}
+
+// the arguments to pair chats, shared by `new` and `add-chat`
+fn pairing_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![Arg::with_name("channel")
+             .long("channel")
+             .value_name("@CHANNEL")
+             .help("pairs with the given channel instead of listening; the bot must be \
+                    administrator of it")
+             .takes_value(true),
+         Arg::with_name("timeout")
+             .long("timeout")
+             .value_name("SECONDS")
+             .help("how long the pairing link is valid; defaults to 600")
+             .takes_value(true),
+         Arg::with_name("no-qr")
+             .long("no-qr")
+             .help("does not show the pairing link as qr code"),
+         Arg::with_name("chat-id")
+             .long("chat-id")
+             .value_name("ID")
+             .help("uses the given chat without pairing; only checks the bot and sends a test \
+                    message. may be given multiple times")
+             .conflicts_with("channel")
+             .multiple(true)
+             .number_of_values(1)
+             .takes_value(true)]
+}
+
+// the options of the send and file subcommands
+fn one_shot_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![Arg::with_name("name")
+             .short("n")
+             .long("name")
+             .value_name("CONNECTION NAME")
+             .help("connection to use; the default if not given")
+             .takes_value(true),
+         Arg::with_name("parse-mode")
+             .long("parse-mode")
+             .value_name("MODE")
+             .help("how telegram formats the text; replaces the mode set for the connection")
+             .possible_values(&["markdown", "html", "none"])
+             .takes_value(true),
+         Arg::with_name("silent")
+             .long("silent")
+             .help("sends without a notification sound"),
+         Arg::with_name("reply-to")
+             .long("reply-to")
+             .value_name("MESSAGE ID")
+             .help("id of a message in the chat to reply to; \
+                    only for connections with a single chat")
+             .takes_value(true)]
+}
+
+// resolves the connection and options for the send and file subcommands
+fn one_shot_setup(matches: &clap::ArgMatches,
+                  config: &Config,
+                  connection: Option<&str>,
+                  topic: Option<&str>)
+                  -> Result<(String, Vec<Recipient>, SendOptions)> {
+    let c = try!(config.find(matches.value_of("name").or(connection)));
+    let token = try!(c.resolve_token());
+    let topic = match topic {
+        Some(t) => Some(try!(c.resolve_topic(t))),
+        None => None,
+    };
+
+    let mut options = c.send_options();
+    if let Some(parse_mode) = matches.value_of("parse-mode") {
+        options.parse_mode = teleecho::config::api_parse_mode(parse_mode);
+    }
+    options.silent = matches.is_present("silent");
+    if let Some(reply_to) = matches.value_of("reply-to") {
+        if c.chats.len() != 1 {
+            return Err(ErrorKind::ReplyToSeveralChats.into());
+        }
+        options.reply_to = Some(try!(reply_to.parse::<i64>().map_err(|_| {
+            Error::from(ErrorKind::ConfigInvalidValue(String::from("reply-to"),
+                                                      String::from(reply_to)))
+        })));
+    }
+
+    Ok((token, c.recipients(topic), options))
+}
+
+// sends the text to every chat of the connection and prints the chat and message ids
+fn subcommand_send(matches: &clap::ArgMatches,
+                   config: &Config,
+                   connection: Option<&str>,
+                   topic: Option<&str>)
+                   -> Result<()> {
+    let (token, recipients, options) = try!(one_shot_setup(matches, config, connection, topic));
+    let api = try!(BotApi::from_token(&token));
+    let text = matches.values_of("text").unwrap().collect::<Vec<&str>>().join(" ");
 
-    // do not allow whitespace in connection name
-    let name_without_whitespace = name.split_whitespace().collect::<Vec<&str>>().join("-");
+    for recipient in recipients {
+        let sent = try!(api.send_text(recipient, &text, &options)
+            .map_err(|e| redact(e, &token)));
+        println!("{} {}", sent.chat_id, sent.message_id);
+    }
+    Ok(())
+}
 
-    match config.get(Some(&name_without_whitespace)) {
-        Ok(_) => return Err("name already taken!".into()),
-        Err(_) => {}
+// uploads the files to every chat of the connection and prints the chat and message ids;
+// several files are sent as albums of at most 10 files
+fn subcommand_file(matches: &clap::ArgMatches,
+                   config: &Config,
+                   connection: Option<&str>,
+                   topic: Option<&str>)
+                   -> Result<()> {
+    let (token, recipients, options) = try!(one_shot_setup(matches, config, connection, topic));
+    let api = try!(BotApi::from_token(&token));
+
+    // every file is read once, however many chats it is sent to
+    let mut files = vec![];
+    for path in matches.values_of("path").unwrap() {
+        files.push(try!(teleecho::botapi::Upload::read(std::path::Path::new(path))));
     }
+    let photo = matches.is_present("photo");
 
-    let (token, id) = try!(teleecho::teleecho::register_connection(token));
-    try!(config.add_entry(name_without_whitespace.clone(), token, id));
-    try!(config.save_to(&mut f));
+    for recipient in recipients {
+        // the caption belongs to the first album only
+        let mut caption = matches.value_of("caption");
+        for group in files.chunks(10) {
+            let sent = if group.len() == 1 {
+                vec![try!(api.send_file(recipient, &group[0], photo, caption, &options)
+                         .map_err(|e| redact(e, &token)))]
+            } else {
+                try!(api.send_file_group(recipient, group, photo, caption, &options)
+                    .map_err(|e| redact(e, &token)))
+            };
+            caption = None;
 
-    println!("new connection successfully created: {}",
-             name_without_whitespace);
+            for m in sent {
+                println!("{} {}", m.chat_id, m.message_id);
+            }
+        }
+    }
     Ok(())
 }
 
+fn parse_chat_id(id: &str) -> Result<i64> {
+    id.parse::<i64>()
+        .map_err(|_| ErrorKind::InvalidArgument(String::from("chat id"), id.to_string()).into())
+}
+
+// pairs the chats for the given token as requested on the command line:
+// verifies the given chat ids, pairs with the given channel or listens for the pairing link
+fn pair_chats(matches: &clap::ArgMatches, token: &str) -> Result<Vec<pairing::Pairing>> {
+    use std::io::Write;
+
+    let timeout = match matches.value_of("timeout") {
+        Some(t) => {
+            try!(t.parse::<u64>().map_err(|_| {
+                Error::from(ErrorKind::InvalidArgument(String::from("timeout in seconds"),
+                                                       t.to_string()))
+            }))
+        }
+        None => pairing::DEFAULT_PAIRING_TIMEOUT,
+    };
+
+    if let Some(ids) = matches.values_of("chat-id") {
+        let mut pairings = vec![];
+        for id in ids {
+            let id = try!(parse_chat_id(id));
+            pairings.push(try!(pairing::verify_chat(token, id)));
+        }
+        return Ok(pairings);
+    }
+
+    // nothing is written before pairing finished, so cancelling just exits;
+    // afterwards Ctrl-C is ignored, so saving the connection is not interrupted
+    PAIRING.store(true, Ordering::SeqCst);
+    try!(ctrlc::set_handler(|| {
+            if PAIRING.load(Ordering::SeqCst) {
+                let _ = writeln!(std::io::stderr(), "\npairing cancelled");
+                std::process::exit(130);
+            }
+        })
+        .map_err(|e| Error::from(ErrorKind::SignalHandler(format!("{}", e)))));
+
+    let pairing = pair_interactively(matches, token, timeout);
+    PAIRING.store(false, Ordering::SeqCst);
+    Ok(vec![try!(pairing)])
+}
+
+// pairs with the given channel or listens for the pairing link,
+// and shows who paired to be confirmed
+fn pair_interactively(matches: &clap::ArgMatches,
+                      token: &str,
+                      timeout: u64)
+                      -> Result<pairing::Pairing> {
+    use std::io::Write;
+
+    let (pairing, listened) = match matches.value_of("channel") {
+        Some(channel) => (try!(pairing::register_channel(token, channel)), false),
+        None => {
+            (try!(pairing::register_connection(token,
+                                                         timeout,
+                                                         !matches.is_present("no-qr"))),
+             true)
+        }
+    };
+
+    // show who paired, so a stranger that got hold of the link is noticed
+    if let Some((ref user, id)) = pairing.user {
+        try!(writeln!(std::io::stderr(), "paired by {}, user id {}", user, id));
+    }
+    try!(writeln!(std::io::stderr(),
+                  "paired with {} chat {} {}",
+                  pairing.chat_type,
+                  pairing.chat_id,
+                  pairing.chat_title));
+    if let Some(thread_id) = pairing.thread_id {
+        try!(writeln!(std::io::stderr(),
+                      "in topic {} {}",
+                      thread_id,
+                      pairing.topic_name.as_ref().map(|n| n.as_str()).unwrap_or("")));
+    }
+
+    // anyone who got hold of the link could have paired, so this must be confirmed
+    if listened && !try!(confirm("save this connection?")) {
+        return Err(ErrorKind::PairingNotConfirmed.into());
+    }
+
+    Ok(pairing)
+}
+
+// asks the given yes or no question on the terminal; no is the default
+fn confirm(question: &str) -> Result<bool> {
+    use std::io::{BufRead, Write};
+
+    try!(write!(std::io::stderr(), "{} [y/N] ", question));
+
+    let stdin = std::io::stdin();
+    let mut answer = String::new();
+    try!(stdin.lock().read_line(&mut answer));
+
+    let answer = answer.trim().to_lowercase();
+    Ok(answer == "y" || answer == "yes")
+}
+
 fn main() {
     let matches = create_clap_app();
+    set_quiet(matches.is_present("quiet"));
+
+    // ask and read exit with the answer, so every error before it, even with
+    // the config file, exits with the same status
+    let config_failure = match matches.subcommand_name() {
+        Some("ask") | Some("read") => ask::EXIT_FAILED,
+        _ => EXIT_CONFIG,
+    };
 
     // at first get the name of the config file, or if none specified the default path
     let config_file: std::path::PathBuf = match matches.value_of("config") {
         Some(t) => std::path::PathBuf::from(t),
         None => {
-            match std::env::home_dir() {
-                Some(mut path) => {
-                    path.push(".teleecho.conf");
-                    path
-                }
-                None => {
-                    println!("error while retrieving home directory");
-                    return;
-                }
+            match Config::default_path() {
+                Some(path) => path,
+                None => exit_with_error("error while retrieving home directory", config_failure),
             }
         }
     };
 
     // then get the name of the connection (None is not specified)
     let connection = matches.value_of("connection");
+    let topic = matches.value_of("topic");
+
+    // the doctor does its own opening and parsing, as it should report a missing
+    // or broken config file instead of creating or failing on it
+    if let Some(matches) = matches.subcommand_matches("doctor") {
+        exit_on_err!(doctor::run(&config_file, matches.value_of("name")));
+        return;
+    }
 
     // now try to open/create the config file
-    let mut f = unwrap_or_return!(OpenOptions::new()
-                                      .read(true)
-                                      .write(true)
-                                      .create(true)
-                                      .append(false)
-                                      .open(&config_file),
-                                  "while opening config file");
+    let mut f = unwrap_or_exit!(open_config_file(&config_file),
+                                "while opening config file",
+                                config_failure);
 
     // if successfully opened, try to parse the config file to a config object
-    let mut config = unwrap_or_return!(Config::parse(&mut f), "while parsing config file");
+    let mut config = unwrap_or_exit!(Config::parse(&mut f),
+                                     "while parsing config file",
+                                     config_failure);
+
+    // the file may contain tokens, so make sure no one else can read it
+    unwrap_or_exit!(config.check_permissions(&f, &config_file),
+                    "while checking config file",
+                    config_failure);
+
+    let answer = if let Some(matches) = matches.subcommand_matches("ask") {
+        Some(subcommand_ask(&matches, &config, connection, topic))
+    } else if let Some(matches) = matches.subcommand_matches("read") {
+        Some(subcommand_read(&matches, &config, connection))
+    } else {
+        None
+    };
+    if let Some(answer) = answer {
+        let status = match answer {
+            Ok(status) => status,
+            Err(e) => {
+                use std::io::Write;
+                let _ = writeln!(std::io::stderr(), "error: {}", e);
+                ask::EXIT_FAILED
+            }
+        };
+        std::process::exit(status);
+    }
 
     // handle the new subcommand
     if let Some(matches) = matches.subcommand_matches("new") {
-        print_err!(subcommand_new(&matches, &mut config, &mut f));
+        exit_on_err!(subcommand_new(&matches, &mut config, &config_file));
+    }
+    // handle the send subcommand
+    else if let Some(matches) = matches.subcommand_matches("send") {
+        exit_on_err!(subcommand_send(&matches, &config, connection, topic));
+    }
+    // handle the file subcommand
+    else if let Some(matches) = matches.subcommand_matches("file") {
+        exit_on_err!(subcommand_file(&matches, &config, connection, topic));
     }
     // handle the list subcommand
-    else if let Some(_) = matches.subcommand_matches("list") {
-        config.list();
+    else if let Some(matches) = matches.subcommand_matches("list") {
+        exit_on_err!(subcommand_list(&matches, &mut config, &config_file));
     }
     // handle the remove subcommand
     else if let Some(matches) = matches.subcommand_matches("remove") {
-        print_err!(subcommand_remove(&matches, &mut config, &mut f));
+        exit_on_err!(subcommand_remove(&matches, &mut config, &config_file));
+    }
+    // handle the add-chat subcommand
+    else if let Some(matches) = matches.subcommand_matches("add-chat") {
+        exit_on_err!(subcommand_add_chat(&matches, &mut config, &config_file));
+    }
+    // handle the remove-chat subcommand
+    else if let Some(matches) = matches.subcommand_matches("remove-chat") {
+        exit_on_err!(subcommand_remove_chat(&matches, &mut config, &config_file));
+    }
+    // handle the rename subcommand
+    else if let Some(matches) = matches.subcommand_matches("rename") {
+        exit_on_err!(subcommand_rename(&matches, &mut config, &config_file));
+    }
+    // handle the copy subcommand
+    else if let Some(matches) = matches.subcommand_matches("copy") {
+        exit_on_err!(subcommand_copy(&matches, &mut config, &config_file));
+    }
+    // handle the show subcommand
+    else if let Some(matches) = matches.subcommand_matches("show") {
+        exit_on_err!(config.show(matches.value_of("name")));
+    }
+    // handle the set subcommand
+    else if let Some(matches) = matches.subcommand_matches("set") {
+        exit_on_err!(subcommand_set(&matches, &mut config, &config_file));
+    }
+    // handle the export subcommand
+    else if let Some(matches) = matches.subcommand_matches("export") {
+        exit_on_err!(subcommand_export(&matches, &config));
     }
-    // if no subcommand was specified, start sending
+    // handle the import subcommand
+    else if let Some(matches) = matches.subcommand_matches("import") {
+        exit_on_err!(subcommand_import(&matches, &mut config, &config_file));
+    }
+    // handle the default subcommand
+    else if let Some(matches) = matches.subcommand_matches("default") {
+        exit_on_err!(subcommand_default(&matches, &mut config, &config_file));
+    }
+    // handle the config subcommand
+    else if let Some(matches) = matches.subcommand_matches("config") {
+        exit_on_err!(subcommand_config(&matches, &mut config, &config_file));
+    }
+    // if no subcommand was specified, start sending stdin,
+    // or with run the output of the command
     else {
-        let (token, user) = unwrap_or_return!(config.get(connection),
-                                              "while retrieving connection");
+        let run = matches.subcommand_matches("run");
+        let connection = run.and_then(|m| m.value_of("name")).or(connection);
+
+        let (name, token, chats, recipients, options) = {
+            let c = unwrap_or_exit!(config.find(connection), "while retrieving connection");
+            let token = unwrap_or_exit!(c.resolve_token(), "while retrieving connection");
+
+            // a topic given on the command line replaces the stored one
+            let topic = match topic {
+                Some(t) => Some(unwrap_or_exit!(c.resolve_topic(t), "while retrieving topic")),
+                None => None,
+            };
+
+            (c.name.clone(), token, c.chats.clone(), c.recipients(topic), c.send_options())
+        };
+
+        let mut tp = unwrap_or_exit!(TeleechoProcessor::create_with_options(&token,
+                                                                       &recipients,
+                                                                       options),
+                                     "while creating bot instance");
+
+        // groups that were upgraded to supergroups got a new id, which is stored
+        // right away, as a long running command may never end cleanly. the file
+        // is loaded again, so changes made to it in the meantime are kept
+        {
+            let config_file = config_file.clone();
+            tp.on_migration(move |m| {
+                let stored = Config::load(&config_file).and_then(|mut config| {
+                    if config.migrate_chat(m.from, m.to) {
+                        try!(config.save(&config_file));
+                    }
+                    Ok(())
+                });
+                if let Err(e) = stored {
+                    warn(&format!("could not store the new chat id: {}", e));
+                }
+            });
+        }
+
+        let status = match run {
+            Some(run) => {
+                let command = run.values_of("command").unwrap().collect::<Vec<&str>>();
+                unwrap_or_exit!(run::run(&mut tp, &token, chats, &command),
+                                "while running command")
+            }
+            None => {
+                process_input(&mut tp);
+                0
+            }
+        };
+        let report = tp.close();
+
+        // report every chat that could not be reached once and exit with
+        // a status wrappers can alert on
+        if !report.failures.is_empty() {
+            use std::io::Write;
+
+            for failure in &report.failures {
+                let _ = writeln!(std::io::stderr(),
+                                 "error: could not send to chat {}: {}",
+                                 failure.chat_id,
+                                 failure.error);
+                if let Some(explanation) = teleecho::botapi::explain(&failure.error, &name) {
+                    let _ = writeln!(std::io::stderr(), "       {}", explanation);
+                }
+            }
+            if report.failures.iter().any(|f| teleecho::botapi::is_token_error(&f.error)) {
+                std::process::exit(EXIT_AUTH);
+            }
+            std::process::exit(EXIT_DELIVERY_FAILED);
+        }
 
-        match TeleechoProcessor::create(&token, user) {
-            Ok(mut tp) => process_input(&mut tp),
-            Err(e) => println!("Error while creating bot instance {}", e),
+        // run exits like the command did
+        if status != 0 {
+            std::process::exit(status);
         }
     }
 }
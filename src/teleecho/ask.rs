@@ -0,0 +1,202 @@
+extern crate serde_json;
+
+use self::serde_json::Value;
+use std::time::{Duration, Instant};
+use teleecho::botapi::{BotApi, SentMessage};
+use teleecho::config::ChatTarget;
+use teleecho::error::*;
+use teleecho::listener::{Listener, POLL_TIMEOUT};
+use teleecho::teleecho::{Recipient, SendOptions};
+
+/// the exit status if no choice was made before the timeout, like `timeout(1)`
+pub const EXIT_TIMEOUT: i32 = 124;
+
+/// the exit status if the question could not be asked
+pub const EXIT_FAILED: i32 = 125;
+
+/// the most choices a question can have; the index of the choice is the exit status,
+/// so it must stay below the statuses above
+pub const MAX_CHOICES: usize = 100;
+
+/// parses a duration like `90`, `30s`, `10m` or `2h` into seconds
+pub fn parse_duration(value: &str) -> Result<u64> {
+    let value = value.trim();
+    let (number, factor) = match value.chars().last() {
+        Some('s') => (&value[..value.len() - 1], 1),
+        Some('m') => (&value[..value.len() - 1], 60),
+        Some('h') => (&value[..value.len() - 1], 3600),
+        _ => (value, 1),
+    };
+
+    number.parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(factor))
+        .ok_or_else(|| ErrorKind::InvalidDuration(String::from(value)).into())
+}
+
+/// returns the name of the user who pressed the button for printing
+fn presser_name(query: &Value) -> String {
+    let mut name = query.lookup("from.first_name")
+        .and_then(|n| n.as_str())
+        .unwrap_or("unknown")
+        .to_string();
+    if let Some(username) = query.lookup("from.username").and_then(|n| n.as_str()) {
+        name.push_str(&format!(" (@{})", username));
+    }
+    name
+}
+
+/// replaces the question in every chat with the given text, which also removes
+/// the buttons; this is only cosmetic, so errors are ignored
+fn close_question(api: &BotApi, sent: &[SentMessage], text: &str) {
+    for m in sent {
+        let _ = api.edit_text(m.chat_id, m.message_id, text, &SendOptions::default());
+    }
+}
+
+/// whether the user who pressed the button or sent the message may answer in the
+/// given chat: in a private chat only its user, in a group or channel the user who
+/// paired it. groups added by id without pairing have no known user, so anyone there may answer
+pub fn may_answer(query: &Value, chat: &ChatTarget) -> bool {
+    let from = query.lookup("from.id").and_then(|i| i.as_i64());
+    match chat.user_id {
+        Some(user) => from == Some(user),
+        None if chat.id > 0 => from == Some(chat.id),
+        None => true,
+    }
+}
+
+// the index of the choice if the given update is a button press on one of
+// the sent questions; presses of users who may not answer are answered
+// with a notice and ignored
+fn chosen<'a>(api: &BotApi,
+              update: &'a Value,
+              chats: &[ChatTarget],
+              sent: &[SentMessage],
+              choices: usize)
+              -> Option<(usize, &'a Value)> {
+    let query = match update.find("callback_query") {
+        Some(q) => q,
+        None => return None,
+    };
+
+    let chat_id = query.lookup("message.chat.id").and_then(|i| i.as_i64());
+    let message_id = query.lookup("message.message_id").and_then(|i| i.as_i64());
+    if !sent.iter().any(|m| Some(m.chat_id) == chat_id && Some(m.message_id) == message_id) {
+        return None;
+    }
+
+    if !chats.iter().any(|c| Some(c.id) == chat_id && may_answer(query, c)) {
+        if let Some(id) = query.find("id").and_then(|i| i.as_str()) {
+            let _ = api.answer_callback_query(id, "only the paired user may answer");
+        }
+        return None;
+    }
+
+    query.find("data")
+        .and_then(|d| d.as_str())
+        .and_then(|d| d.parse::<usize>().ok())
+        .and_then(|i| if i < choices { Some((i, query)) } else { None })
+}
+
+/// sends the question with a button for every choice to the given recipients, one
+/// for each of the given chats, and waits until one of the buttons is pressed
+/// there. returns the index of the
+/// choice, or None if no choice was made within `timeout` seconds.
+/// without a timeout this waits until a button is pressed.
+/// at most `MAX_CHOICES` choices are possible
+pub fn ask(token: &str,
+           chats: &[ChatTarget],
+           recipients: &[Recipient],
+           question: &str,
+           choices: &[&str],
+           timeout: Option<u64>)
+           -> Result<Option<usize>> {
+    ask_inner(token, chats, recipients, question, choices, timeout).map_err(|e| redact(e, token))
+}
+
+fn ask_inner(token: &str,
+             chats: &[ChatTarget],
+             recipients: &[Recipient],
+             question: &str,
+             choices: &[&str],
+             timeout: Option<u64>)
+             -> Result<Option<usize>> {
+    if choices.is_empty() {
+        return Err(ErrorKind::AskNoChoices.into());
+    }
+    if choices.len() > MAX_CHOICES {
+        return Err(ErrorKind::AskTooManyChoices(MAX_CHOICES).into());
+    }
+
+    let api = try!(BotApi::from_token(token));
+    let mut listener = Listener::new(BotApi::new(token));
+
+    // buttons pressed before are not answers to this question
+    try!(listener.skip_pending());
+
+    let mut sent = vec![];
+    for recipient in recipients {
+        sent.push(try!(api.send_keyboard(*recipient, question, choices)));
+    }
+
+    let expires = timeout.map(|t| Instant::now() + Duration::from_secs(t));
+    loop {
+        let poll_timeout = match expires {
+            Some(expires) => {
+                let now = Instant::now();
+                if now >= expires {
+                    close_question(&api, &sent, &format!("{}\n\n(no answer in time)", question));
+                    try!(listener.acknowledge());
+                    return Ok(None);
+                }
+
+                // do not poll longer than the question is open
+                ::std::cmp::min((expires - now).as_secs() as u32 + 1, POLL_TIMEOUT)
+            }
+            None => POLL_TIMEOUT,
+        };
+
+        for update in try!(listener.poll_updates(poll_timeout)) {
+            let (choice, query) = match chosen(&api, &update, chats, &sent, choices.len()) {
+                Some(c) => c,
+                None => continue,
+            };
+
+            if let Some(id) = query.find("id").and_then(|i| i.as_str()) {
+                let _ = api.answer_callback_query(id, choices[choice]);
+            }
+            close_question(&api,
+                           &sent,
+                           &format!("{}\n\n{} (by {})",
+                                    question,
+                                    choices[choice],
+                                    presser_name(query)));
+            try!(listener.acknowledge());
+
+            return Ok(Some(choice));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_with_units() {
+        assert_eq!(parse_duration("90").unwrap(), 90);
+        assert_eq!(parse_duration("30s").unwrap(), 30);
+        assert_eq!(parse_duration("10m").unwrap(), 600);
+        assert_eq!(parse_duration(" 2h ").unwrap(), 7200);
+    }
+
+    #[test]
+    fn invalid_durations() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("-5s").is_err());
+        assert!(parse_duration("10d").is_err());
+        assert!(parse_duration("18446744073709551615h").is_err());
+    }
+}
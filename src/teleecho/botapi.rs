@@ -0,0 +1,542 @@
+extern crate hyper;
+extern crate rand;
+extern crate serde_json;
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use self::hyper::header::ContentType;
+use self::rand::Rng;
+use self::serde_json::Value;
+use self::serde_json::builder::{ArrayBuilder, ObjectBuilder};
+use teleecho::error::*;
+use teleecho::teleecho::{Recipient, SendOptions};
+
+/// the number of buttons in a row of a keyboard; more do not fit on a phone
+const MAX_BUTTONS_PER_ROW: usize = 8;
+
+/// a sent text message, as needed to edit it later
+#[derive(Debug, Clone)]
+pub struct SentMessage {
+    pub chat_id: i64,
+    pub message_id: i64,
+
+    /// the text as telegram stored it
+    pub text: String,
+}
+
+impl SentMessage {
+    /// takes the message returned by telegram when sending or editing
+    pub fn from_value(message: &Value) -> Result<SentMessage> {
+        match (message.lookup("chat.id").and_then(|i| i.as_i64()),
+               message.find("message_id").and_then(|i| i.as_i64())) {
+            (Some(chat_id), Some(message_id)) => {
+                Ok(SentMessage {
+                    chat_id: chat_id,
+                    message_id: message_id,
+                    text: message.find("text").and_then(|t| t.as_str()).unwrap_or("").to_string(),
+                })
+            }
+            _ => Err(unexpected_answer("no message")),
+        }
+    }
+}
+
+/// a field of a multipart request, as needed to upload files
+enum Part<'a> {
+    Text(String, String),
+
+    /// field name and the file
+    File(String, &'a Upload),
+}
+
+/// a file read into memory, so it is read only once even if it is uploaded
+/// to several chats
+#[derive(Debug, Clone)]
+pub struct Upload {
+    pub file_name: String,
+    pub content: Vec<u8>,
+}
+
+impl Upload {
+    /// reads the file at the given path
+    pub fn read(path: &Path) -> Result<Upload> {
+        let mut content = vec![];
+        try!(try!(File::open(path)).read_to_end(&mut content));
+
+        let file_name = path.file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or(String::from("file"));
+        Ok(Upload {
+            file_name: file_name,
+            content: content,
+        })
+    }
+}
+
+/// direct access to the telegram bot api.
+/// errors keep the error code and parameters returned by telegram,
+/// so they can be told apart
+pub struct BotApi {
+    token: String,
+    client: hyper::Client,
+}
+
+impl BotApi {
+    pub fn new(token: &str) -> BotApi {
+        BotApi {
+            token: String::from(token),
+            client: hyper::Client::new(),
+        }
+    }
+
+    /// like `new`, but fails if the token is obviously malformed;
+    /// a token is the bot id, a colon and the secret
+    pub fn from_token(token: &str) -> Result<BotApi> {
+        try!(check_token_format(token));
+        Ok(BotApi::new(token))
+    }
+
+    /// calls the given api method with the given json parameters and returns
+    /// the result field of the answer
+    pub fn call(&self, method: &str, params: &Value) -> Result<Value> {
+        let body = try!(serde_json::to_string(params));
+        self.post(method, ContentType::json(), body.as_bytes())
+    }
+
+    /// like `call`, but sends the parameters as multipart form, which is needed
+    /// to upload files
+    fn call_multipart(&self, method: &str, parts: &[Part]) -> Result<Value> {
+        let boundary = rand::thread_rng().gen_ascii_chars().take(32).collect::<String>();
+
+        let mut body = vec![];
+        for part in parts {
+            body.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+            match *part {
+                Part::Text(ref name, ref value) => {
+                    body.extend_from_slice(format!("Content-Disposition: form-data; \
+                                                    name=\"{}\"\r\n\r\n{}",
+                                                   name,
+                                                   value)
+                        .as_bytes());
+                }
+                Part::File(ref name, file) => {
+                    body.extend_from_slice(format!("Content-Disposition: form-data; \
+                                                    name=\"{}\"; filename=\"{}\"\r\n\
+                                                    Content-Type: \
+                                                    application/octet-stream\r\n\r\n",
+                                                   name,
+                                                   header_value(&file.file_name))
+                        .as_bytes());
+                    body.extend_from_slice(&file.content);
+                }
+            }
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+
+        // the boundary only consists of letters and digits, so this always parses
+        let content_type = format!("multipart/form-data; boundary={}", boundary)
+            .parse()
+            .unwrap();
+        self.post(method, ContentType(content_type), &body)
+    }
+
+    /// posts the given body to the given api method and returns the result
+    /// field of the answer
+    fn post(&self, method: &str, content_type: ContentType, body: &[u8]) -> Result<Value> {
+        // the url contains the token, so it must not end up in any error
+        let url = format!("https://api.telegram.org/bot{}/{}", self.token, method);
+
+        let mut response = try!(self.client
+                                    .post(&url)
+                                    .header(content_type)
+                                    .body(body)
+                                    .send()
+                                    .map_err(|e| {
+                                        redact(ErrorKind::Network(format!("{}", e)).into(),
+                                               &self.token)
+                                    }));
+
+        let mut content = String::new();
+        try!(response.read_to_string(&mut content));
+        parse_answer(&content)
+    }
+
+    /// sends a text message to the given recipient with the given options
+    pub fn send_text(&self,
+                     recipient: Recipient,
+                     text: &str,
+                     options: &SendOptions)
+                     -> Result<SentMessage> {
+        let params = send_text_params(recipient, text, options);
+        SentMessage::from_value(&try!(self.call("sendMessage", &params)))
+    }
+
+    /// uploads the given file as document, or as photo which telegram
+    /// shows inline, with an optional caption
+    pub fn send_file(&self,
+                     recipient: Recipient,
+                     file: &Upload,
+                     photo: bool,
+                     caption: Option<&str>,
+                     options: &SendOptions)
+                     -> Result<SentMessage> {
+        let field = if photo { "photo" } else { "document" };
+        let mut parts = file_options(recipient, caption, options);
+        parts.push(Part::File(String::from(field), file));
+
+        let method = if photo { "sendPhoto" } else { "sendDocument" };
+        SentMessage::from_value(&try!(self.call_multipart(method, &parts)))
+    }
+
+    /// uploads 2 to 10 files as an album, which is shown as one message;
+    /// the caption is shown below the first file
+    pub fn send_file_group(&self,
+                           recipient: Recipient,
+                           files: &[Upload],
+                           photo: bool,
+                           caption: Option<&str>,
+                           options: &SendOptions)
+                           -> Result<Vec<SentMessage>> {
+        // the files are referenced as attach://<field name> in the list of media
+        let mut parts = file_options(recipient, None, options);
+        let mut media = ArrayBuilder::new();
+        for (i, file) in files.iter().enumerate() {
+            let field = format!("file{}", i);
+            let mut item = ObjectBuilder::new()
+                .insert("type", if photo { "photo" } else { "document" })
+                .insert("media", format!("attach://{}", field));
+            if let (0, Some(caption)) = (i, caption) {
+                item = item.insert("caption", caption);
+                if let Some(ref parse_mode) = options.parse_mode {
+                    item = item.insert("parse_mode", parse_mode.as_str());
+                }
+            }
+            media = media.push(item.build());
+            parts.push(Part::File(field, file));
+        }
+        parts.push(Part::Text(String::from("media"),
+                              try!(serde_json::to_string(&media.build()))));
+
+        match try!(self.call_multipart("sendMediaGroup", &parts)) {
+            Value::Array(messages) => messages.iter().map(SentMessage::from_value).collect(),
+            _ => Err(unexpected_answer("sendMediaGroup returned no messages")),
+        }
+    }
+
+    /// sends a text message with buttons below it, in rows of at most `MAX_BUTTONS_PER_ROW`;
+    /// pressing a button sends a callback query with the index of the button as data
+    pub fn send_keyboard(&self,
+                         recipient: Recipient,
+                         text: &str,
+                         buttons: &[&str])
+                         -> Result<SentMessage> {
+        let mut rows = ArrayBuilder::new();
+        for (r, chunk) in buttons.chunks(MAX_BUTTONS_PER_ROW).enumerate() {
+            let mut row = ArrayBuilder::new();
+            for (i, button) in chunk.iter().enumerate() {
+                let index = r * MAX_BUTTONS_PER_ROW + i;
+                row = row.push_object(|o| {
+                    o.insert("text", *button).insert("callback_data", index.to_string())
+                });
+            }
+            rows = rows.push(row.build());
+        }
+        let keyboard = ObjectBuilder::new().insert("inline_keyboard", rows.build()).build();
+
+        let mut params = ObjectBuilder::new()
+            .insert("chat_id", recipient.chat_id)
+            .insert("text", text)
+            .insert("reply_markup", keyboard);
+        if let Some(thread_id) = recipient.thread_id {
+            params = params.insert("message_thread_id", thread_id);
+        }
+
+        SentMessage::from_value(&try!(self.call("sendMessage", &params.build())))
+    }
+
+    /// tells telegram a button press was handled; the text is shown briefly to the user
+    pub fn answer_callback_query(&self, id: &str, text: &str) -> Result<()> {
+        try!(self.call("answerCallbackQuery",
+                       &ObjectBuilder::new()
+                            .insert("callback_query_id", id)
+                            .insert("text", text)
+                            .build()));
+        Ok(())
+    }
+
+    /// replaces the text of the given message
+    pub fn edit_text(&self,
+                     chat_id: i64,
+                     message_id: i64,
+                     text: &str,
+                     options: &SendOptions)
+                     -> Result<SentMessage> {
+        let params = edit_text_params(chat_id, message_id, text, options);
+        SentMessage::from_value(&try!(self.call("editMessageText", &params)))
+    }
+
+    /// returns information about the bot itself
+    pub fn get_me(&self) -> Result<Value> {
+        self.call("getMe", &ObjectBuilder::new().build())
+    }
+
+    /// returns information about the given chat
+    pub fn get_chat(&self, chat_id: i64) -> Result<Value> {
+        self.call("getChat", &ObjectBuilder::new().insert("chat_id", chat_id).build())
+    }
+
+    /// returns information about the channel or group with the given @username
+    pub fn get_chat_by_username(&self, username: &str) -> Result<Value> {
+        self.call("getChat", &ObjectBuilder::new().insert("chat_id", username).build())
+    }
+
+    /// returns the membership of the given user in the given chat
+    pub fn get_chat_member(&self, chat_id: i64, user_id: i64) -> Result<Value> {
+        self.call("getChatMember",
+                  &ObjectBuilder::new()
+                       .insert("chat_id", chat_id)
+                       .insert("user_id", user_id)
+                       .build())
+    }
+
+    /// sets the commands suggested in the chats with the bot,
+    /// given as pairs of command without slash and description
+    pub fn set_my_commands(&self, commands: &[(&str, &str)]) -> Result<()> {
+        let list = commands.iter().fold(ArrayBuilder::new(), |list, &(command, description)| {
+            list.push_object(|o| o.insert("command", command).insert("description", description))
+        });
+
+        try!(self.call("setMyCommands",
+                       &ObjectBuilder::new().insert("commands", list.build()).build()));
+        Ok(())
+    }
+
+    /// returns the info needed to download the file with the given id
+    pub fn get_file(&self, file_id: &str) -> Result<Value> {
+        self.call("getFile", &ObjectBuilder::new().insert("file_id", file_id).build())
+    }
+
+    /// downloads the file at the given path as returned by `get_file`
+    pub fn download(&self, file_path: &str) -> Result<Vec<u8>> {
+        // as for calls the url contains the token
+        let url = format!("https://api.telegram.org/file/bot{}/{}", self.token, file_path);
+        let mut response = try!(self.client
+                                    .get(&url)
+                                    .send()
+                                    .map_err(|e| {
+                                        redact(ErrorKind::Network(format!("{}", e)).into(),
+                                               &self.token)
+                                    }));
+
+        if !response.status.is_success() {
+            return Err(ErrorKind::DownloadFailed(format!("{}", response.status)).into());
+        }
+
+        let mut content = vec![];
+        try!(response.read_to_end(&mut content));
+        Ok(content)
+    }
+
+    /// long polls for new updates starting at the given offset;
+    /// returns after at most `timeout` seconds, possibly without updates
+    pub fn get_updates(&self, offset: i64, timeout: u32) -> Result<Vec<Value>> {
+        let result = try!(self.call("getUpdates",
+                                    &ObjectBuilder::new()
+                                         .insert("offset", offset)
+                                         .insert("timeout", timeout)
+                                         .build()));
+        match result {
+            Value::Array(updates) => Ok(updates),
+            _ => Err(unexpected_answer("getUpdates returned no updates")),
+        }
+    }
+}
+
+/// fails if the given token is obviously malformed;
+/// a token is the bot id, a colon and the secret
+pub fn check_token_format(token: &str) -> Result<()> {
+    let mut parts = token.splitn(2, ':');
+    let valid = match (parts.next(), parts.next()) {
+        (Some(id), Some(secret)) => {
+            id.len() > 0 && id.chars().all(|c| c.is_digit(10)) && secret.len() > 0
+        }
+        _ => false,
+    };
+
+    if valid {
+        Ok(())
+    } else {
+        Err(ErrorKind::InvalidTokenFormat.into())
+    }
+}
+
+/// returns the result field of the given answer of the api, or the error
+/// with the error code and parameters returned by telegram
+pub fn parse_answer(content: &str) -> Result<Value> {
+    let answer: Value = try!(serde_json::from_str(content));
+
+    if answer.find("ok").and_then(|o| o.as_bool()) == Some(true) {
+        Ok(answer.find("result").cloned().unwrap_or(Value::Null))
+    } else {
+        let code = answer.find("error_code").and_then(|c| c.as_i64()).unwrap_or(0);
+        let description = answer.find("description")
+            .and_then(|d| d.as_str())
+            .unwrap_or("")
+            .to_string();
+        let migrate_to = answer.lookup("parameters.migrate_to_chat_id")
+            .and_then(|m| m.as_i64());
+
+        Err(ErrorKind::TelegramApi(code, description, migrate_to).into())
+    }
+}
+
+/// makes the given text safe to quote in a multipart header: quotes would end
+/// the value and line breaks the header
+fn header_value(text: &str) -> String {
+    text.replace('"', "'").replace('\r', "").replace('\n', " ")
+}
+
+/// the parameters of `sendMessage`
+pub fn send_text_params(recipient: Recipient, text: &str, options: &SendOptions) -> Value {
+    let mut params = ObjectBuilder::new()
+        .insert("chat_id", recipient.chat_id)
+        .insert("text", text)
+        .insert("disable_web_page_preview", options.disable_web_page_preview)
+        .insert("disable_notification", options.silent);
+    if let Some(thread_id) = recipient.thread_id {
+        params = params.insert("message_thread_id", thread_id);
+    }
+    if let Some(ref parse_mode) = options.parse_mode {
+        params = params.insert("parse_mode", parse_mode.as_str());
+    }
+    if let Some(reply_to) = options.reply_to {
+        params = params.insert("reply_to_message_id", reply_to)
+            .insert("allow_sending_without_reply", true);
+    }
+    params.build()
+}
+
+/// the parameters of `editMessageText`
+pub fn edit_text_params(chat_id: i64,
+                        message_id: i64,
+                        text: &str,
+                        options: &SendOptions)
+                        -> Value {
+    let mut params = ObjectBuilder::new()
+        .insert("chat_id", chat_id)
+        .insert("message_id", message_id)
+        .insert("text", text)
+        .insert("disable_web_page_preview", options.disable_web_page_preview);
+    if let Some(ref parse_mode) = options.parse_mode {
+        params = params.insert("parse_mode", parse_mode.as_str());
+    }
+    params.build()
+}
+
+/// the parameters of a file upload besides the file itself
+fn file_options<'a>(recipient: Recipient,
+                    caption: Option<&str>,
+                    options: &SendOptions)
+                    -> Vec<Part<'a>> {
+    let mut parts = vec![Part::Text(String::from("chat_id"), recipient.chat_id.to_string()),
+                         Part::Text(String::from("disable_notification"),
+                                    options.silent.to_string())];
+    if let Some(thread_id) = recipient.thread_id {
+        parts.push(Part::Text(String::from("message_thread_id"), thread_id.to_string()));
+    }
+    if let Some(caption) = caption {
+        parts.push(Part::Text(String::from("caption"), String::from(caption)));
+        if let Some(ref parse_mode) = options.parse_mode {
+            parts.push(Part::Text(String::from("parse_mode"), parse_mode.clone()));
+        }
+    }
+    if let Some(reply_to) = options.reply_to {
+        parts.push(Part::Text(String::from("reply_to_message_id"), reply_to.to_string()));
+        parts.push(Part::Text(String::from("allow_sending_without_reply"),
+                              String::from("true")));
+    }
+    parts
+}
+
+/// returns the message of an update; posts in channels count as messages too
+pub fn update_message(update: &Value) -> Option<&Value> {
+    update.find("message").or(update.find("channel_post"))
+}
+
+/// the error if telegram answered, but not with what was asked for
+pub fn unexpected_answer(reason: &str) -> Error {
+    ErrorKind::TelegramUnexpectedAnswer(String::from(reason)).into()
+}
+
+/// whether the token was rejected by telegram, so no chat can be reached
+pub fn is_token_error(err: &Error) -> bool {
+    match *err.kind() {
+        ErrorKind::TelegramApi(401, _, _) |
+        ErrorKind::TelegramApi(404, _, _) => true,
+        _ => false,
+    }
+}
+
+/// whether retrying can not help with this error: the token is invalid,
+/// the bot was blocked or removed, or the chat does not exist anymore
+pub fn is_fatal(err: &Error) -> bool {
+    match *err.kind() {
+        ErrorKind::TelegramApi(403, _, _) => true,
+        ErrorKind::TelegramApi(400, ref description, _) => {
+            description.to_lowercase().contains("chat not found")
+        }
+        _ => is_token_error(err),
+    }
+}
+
+/// given an error this returns an explanation of what went wrong and how
+/// to fix it, if the error is a known telegram or network error.
+/// `connection` is the name of the affected connection
+pub fn explain(err: &Error, connection: &str) -> Option<String> {
+    match *err.kind() {
+        ErrorKind::TelegramApi(_, _, Some(new_id)) => {
+            Some(format!("chat migrated to supergroup {}; run `teleecho set {} chat_id {}`",
+                         new_id,
+                         connection,
+                         new_id))
+        }
+        ErrorKind::TelegramApi(401, _, _) |
+        ErrorKind::TelegramApi(404, _, _) => {
+            Some(format!("the token is invalid or was revoked; get a new one from @botfather \
+                          and run `teleecho set {} token <TOKEN>`",
+                         connection))
+        }
+        ErrorKind::TelegramApi(403, ref description, _) => {
+            let description = description.to_lowercase();
+            if description.contains("blocked") {
+                Some(String::from("bot was blocked by the user; unblock it in the telegram \
+                                   chat"))
+            } else if description.contains("kicked") {
+                Some(String::from("bot was removed from the group or channel; add it again"))
+            } else if description.contains("deactivated") {
+                Some(String::from("the user account was deleted"))
+            } else if description.contains("not a member") {
+                Some(String::from("bot is not a member of the chat; add it to the group or \
+                                   channel"))
+            } else {
+                Some(String::from("bot is not allowed to write to the chat"))
+            }
+        }
+        ErrorKind::TelegramApi(400, ref description, _) if description.to_lowercase()
+            .contains("chat not found") => {
+            Some(format!("chat not found; it was deleted or the user never started the bot. \
+                          pair again or run `teleecho set {} chat_id <ID>`",
+                         connection))
+        }
+        ErrorKind::TelegramApi(429, _, _) => {
+            Some(String::from("too many requests; telegram is rate limiting this bot"))
+        }
+        ErrorKind::Network(_) => {
+            Some(String::from("could not reach api.telegram.org; check the network connection \
+                               and any proxy or firewall"))
+        }
+        _ => None,
+    }
+}
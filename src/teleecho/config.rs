@@ -1,16 +1,581 @@
+use std::io;
 use std::io::prelude::*;
-use std::fs::File;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::collections::BTreeMap;
 extern crate serde_json;
+extern crate rpassword;
 
 use teleecho::error::*;
+use teleecho::teleecho::{Recipient, SendOptions};
+use teleecho::envelope;
+
+/// a single bot to chat connection as stored in the config file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Connection {
+    pub name: String,
+
+    /// the bot token itself, or a reference to an environment variable
+    /// in the form `${VARIABLE}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+
+    /// a shell command whose output is the bot token,
+    /// e.g. `pass show bots/backup`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_command: Option<String>,
+
+    /// the chats every message is sent to
+    #[serde(default)]
+    pub chats: Vec<ChatTarget>,
+
+    /// older versions stored a single chat; only read to convert it to `chats`
+    #[serde(default, skip_serializing, rename = "chat_id")]
+    legacy_chat_id: Option<i64>,
+
+    /// see `legacy_chat_id`
+    #[serde(default, skip_serializing, rename = "chat_type")]
+    legacy_chat_type: Option<String>,
+
+    /// how telegram should parse the sent text; `markdown` or `html`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parse_mode: Option<String>,
+
+    /// if set, no previews for links in the sent text are shown
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disable_link_preview: Option<bool>,
+
+    /// the bot as returned by getMe; only a cache, filled on demand
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bot: Option<BotInfo>,
+
+    /// names for forum topics, so they can be chosen by name with `--topic`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub topics: BTreeMap<String, i64>,
+}
+
+/// a chat messages of a connection are sent to
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChatTarget {
+    pub id: i64,
+
+    /// the type of the chat: `private`, `group`, `supergroup` or `channel`;
+    /// recorded while pairing or cached on demand
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chat_type: Option<String>,
+
+    /// the forum topic of a supergroup to send to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread_id: Option<i64>,
+
+    /// the user who paired a group or channel; only this user may answer `ask` there
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<i64>,
+}
+
+impl ChatTarget {
+    pub fn new(id: i64, chat_type: Option<String>) -> ChatTarget {
+        ChatTarget {
+            id: id,
+            chat_type: chat_type,
+            thread_id: None,
+            user_id: None,
+        }
+    }
+
+    /// only groups can have topics; private chats and channels can not
+    fn may_have_topics(&self) -> bool {
+        match self.chat_type.as_ref().map(|t| t.as_str()) {
+            Some("private") | Some("channel") => false,
+            _ => self.id < 0,
+        }
+    }
+}
+
+/// the identity of a bot
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BotInfo {
+    pub id: i64,
+    pub username: String,
+}
+
+/// a connection as printed by `list --json`; never contains the token
+#[derive(Serialize)]
+struct ListEntry<'a> {
+    name: &'a str,
+    default: bool,
+    bot_id: Option<i64>,
+    bot_username: Option<&'a str>,
+    chats: &'a [ChatTarget],
+    topics: &'a BTreeMap<String, i64>,
+    parse_mode: Option<&'a str>,
+    disable_link_preview: Option<bool>,
+}
+
+/// the keys that can be changed with `Connection::set`
+pub const CONNECTION_KEYS: &'static [&'static str] = &["token",
+                                                       "token_command",
+                                                       "chat_id",
+                                                       "chat_ids",
+                                                       "thread_id",
+                                                       "topic:<NAME>",
+                                                       "parse_mode",
+                                                       "disable_link_preview"];
+
+/// checks whether `key` is one of `CONNECTION_KEYS`, `topic:<NAME>` standing for any
+/// non-empty topic name
+pub fn is_connection_key(key: &str) -> bool {
+    if key.starts_with("topic:") {
+        key.len() > "topic:".len()
+    } else {
+        CONNECTION_KEYS.contains(&key)
+    }
+}
+
+impl Connection {
+    /// creates a connection with the given token and chats and no further options
+    pub fn new(name: String,
+               token: Option<String>,
+               token_command: Option<String>,
+               chats: Vec<ChatTarget>)
+               -> Connection {
+        Connection {
+            name: name,
+            token: token,
+            token_command: token_command,
+            chats: chats,
+            legacy_chat_id: None,
+            legacy_chat_type: None,
+            parse_mode: None,
+            disable_link_preview: None,
+            bot: None,
+            topics: BTreeMap::new(),
+        }
+    }
+
+    /// moves the single chat of older config files into `chats`
+    fn migrate(&mut self) {
+        if let Some(id) = self.legacy_chat_id.take() {
+            if !self.chats.iter().any(|c| c.id == id) {
+                self.chats.insert(0, ChatTarget::new(id, self.legacy_chat_type.take()));
+            }
+        }
+        self.legacy_chat_type = None;
+    }
+
+    /// the ids of all chats messages are sent to
+    pub fn chat_ids(&self) -> Vec<i64> {
+        self.chats.iter().map(|c| c.id).collect()
+    }
+
+    /// given a topic id or a topic name set with `topic:<NAME>`,
+    /// this returns the id of the topic
+    pub fn resolve_topic(&self, topic: &str) -> Result<i64> {
+        match topic.parse::<i64>() {
+            Ok(id) => Ok(id),
+            Err(_) => {
+                match self.topics.get(topic) {
+                    Some(id) => Ok(*id),
+                    None => {
+                        Err(ErrorKind::ConfigUnknownTopic(self.name.clone(), topic.to_string())
+                                .into())
+                    }
+                }
+            }
+        }
+    }
+
+    /// the chats and topics to send to; if a topic is given, this replaces
+    /// the stored topic of every chat that can have topics
+    pub fn recipients(&self, topic: Option<i64>) -> Vec<Recipient> {
+        self.chats
+            .iter()
+            .map(|c| {
+                Recipient {
+                    chat_id: c.id,
+                    thread_id: if c.may_have_topics() { topic.or(c.thread_id) } else { None },
+                }
+            })
+            .collect()
+    }
+
+    /// adds another chat to send to; fails if it is already there
+    pub fn add_chat(&mut self, chat: ChatTarget) -> Result<()> {
+        if self.chats.iter().any(|c| c.id == chat.id) {
+            return Err(ErrorKind::ConfigChatExists(self.name.clone(), chat.id).into());
+        }
+        self.chats.push(chat);
+        Ok(())
+    }
+
+    /// removes the chat with the given id; the last chat can not be removed
+    pub fn remove_chat(&mut self, id: i64) -> Result<()> {
+        match self.chats.iter().position(|c| c.id == id) {
+            Some(_) if self.chats.len() == 1 => {
+                Err(ErrorKind::ConfigLastChat.into())
+            }
+            Some(index) => {
+                self.chats.remove(index);
+                Ok(())
+            }
+            None => Err(ErrorKind::ConfigChatNotFound(self.name.clone(), id).into()),
+        }
+    }
+
+    /// sets the given key to the given value; `none` unsets optional keys.
+    /// fails if the key is unknown or the value is not valid for the key
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let unset = value == "none";
+        match key {
+            "token" => {
+                self.token = if unset { None } else { Some(String::from(value)) };
+                if !unset {
+                    self.token_command = None;
+                }
+            }
+            "token_command" => {
+                self.token_command = if unset { None } else { Some(String::from(value)) };
+            }
+            "chat_id" | "chat_ids" => {
+                // a single id replaces all chats, a comma separated list sets them all
+                let mut chats = vec![];
+                for id in value.split(',') {
+                    let id = try!(id.trim().parse::<i64>().map_err(|_| {
+                        ErrorKind::ConfigInvalidValue(key.to_string(), value.to_string())
+                    }));
+                    // keep the type, topic and user of chats that stay
+                    let chat = match self.chats.iter().find(|c| c.id == id) {
+                        Some(chat) => chat.clone(),
+                        None => ChatTarget::new(id, None),
+                    };
+                    chats.push(chat);
+                }
+                if chats.len() > 1 && key == "chat_id" {
+                    return Err(ErrorKind::ConfigInvalidValue(key.to_string(), value.to_string())
+                                   .into());
+                }
+                self.chats = chats;
+            }
+            "thread_id" => {
+                let thread_id = if unset {
+                    None
+                } else {
+                    Some(try!(self.resolve_topic(value).map_err(|_| {
+                        ErrorKind::ConfigInvalidValue(key.to_string(), value.to_string())
+                    })))
+                };
+                for chat in self.chats.iter_mut().filter(|c| c.may_have_topics()) {
+                    chat.thread_id = thread_id;
+                }
+            }
+            _ if key.starts_with("topic:") && key.len() > "topic:".len() => {
+                let name = key["topic:".len()..].to_string();
+                if unset {
+                    self.topics.remove(&name);
+                } else {
+                    let id = try!(value.parse::<i64>().map_err(|_| {
+                        ErrorKind::ConfigInvalidValue(key.to_string(), value.to_string())
+                    }));
+                    self.topics.insert(name, id);
+                }
+            }
+            "parse_mode" => {
+                self.parse_mode = match value {
+                    "none" => None,
+                    "markdown" | "html" => Some(String::from(value)),
+                    _ => {
+                        return Err(ErrorKind::ConfigInvalidValue(key.to_string(),
+                                                                 value.to_string())
+                                       .into())
+                    }
+                };
+            }
+            "disable_link_preview" => {
+                self.disable_link_preview = match value {
+                    "none" => None,
+                    "true" => Some(true),
+                    "false" => Some(false),
+                    _ => {
+                        return Err(ErrorKind::ConfigInvalidValue(key.to_string(),
+                                                                 value.to_string())
+                                       .into())
+                    }
+                };
+            }
+            _ => return Err(ErrorKind::ConfigUnknownKey(key.to_string()).into()),
+        }
+
+        // the cached information may not be valid anymore
+        match key {
+            "token" | "token_command" => self.bot = None,
+            _ => {}
+        }
+
+        if self.token.is_none() && self.token_command.is_none() {
+            return Err(ErrorKind::ConfigNoToken(self.name.clone()).into());
+        }
+
+        Ok(())
+    }
+
+    /// a printable description of where the token comes from;
+    /// a token stored as is will be masked
+    pub fn token_source(&self) -> String {
+        if let Some(ref command) = self.token_command {
+            return format!("command `{}`", command);
+        }
+        match self.token {
+            Some(ref token) if env_reference(token).is_some() => {
+                format!("environment {}", token.trim())
+            }
+            Some(ref token) => mask_token(token),
+            None => String::from("none"),
+        }
+    }
+
+    /// the options that are used while sending messages
+    pub fn send_options(&self) -> SendOptions {
+        SendOptions {
+            parse_mode: self.parse_mode.as_ref().and_then(|p| api_parse_mode(p)),
+            disable_web_page_preview: self.disable_link_preview.unwrap_or(false),
+            silent: false,
+            reply_to: None,
+        }
+    }
+
+    /// returns the actual bot token of this connection, either by running
+    /// the token command, by reading the referenced environment variable
+    /// or by just returning the stored token.
+    /// the token itself is never part of the returned error
+    pub fn resolve_token(&self) -> Result<String> {
+        if let Some(ref command) = self.token_command {
+            return run_token_command(&self.name, command);
+        }
+
+        match self.token {
+            Some(ref token) => {
+                match env_reference(token) {
+                    Some(var) => {
+                        match ::std::env::var(var) {
+                            Ok(t) => Ok(t.trim().to_string()),
+                            Err(_) => {
+                                Err(ErrorKind::ConfigTokenEnvMissing(self.name.clone(),
+                                                                     var.to_string())
+                                        .into())
+                            }
+                        }
+                    }
+                    None => Ok(token.clone()),
+                }
+            }
+            None => Err(ErrorKind::ConfigNoToken(self.name.clone()).into()),
+        }
+    }
+
+    /// true if the token is stored as is in the config file
+    pub fn has_literal_token(&self) -> bool {
+        self.token_command.is_none() &&
+        self.token.as_ref().map(|t| env_reference(t).is_none()).unwrap_or(false)
+    }
+}
+
+/// if the given token is of the form `${VARIABLE}` this returns the variable name
+fn env_reference(token: &str) -> Option<&str> {
+    let token = token.trim();
+    if token.starts_with("${") && token.ends_with("}") && token.len() > 3 {
+        Some(&token[2..token.len() - 1])
+    } else {
+        None
+    }
+}
+
+/// the terminal as stdin of a token command, as stdin of teleecho is the input to send
+fn token_command_stdin() -> Stdio {
+    match File::open("/dev/tty") {
+        Ok(tty) => Stdio::from(tty),
+        Err(_) => Stdio::null(),
+    }
+}
+
+/// runs the given command with the shell and returns the first line
+/// of its output as token
+fn run_token_command(name: &str, command: &str) -> Result<String> {
+    let output = try!(Command::new("sh")
+                          .arg("-c")
+                          .arg(command)
+                          .stdin(token_command_stdin())
+                          .stderr(Stdio::inherit())
+                          .output()
+                          .map_err(|e| {
+                              Error::from(ErrorKind::ConfigTokenCommandFailed(name.to_string(),
+                                                                              format!("{}", e)))
+                          }));
+
+    // the output is deliberately not part of the error,
+    // as it might contain the token
+    if !output.status.success() {
+        return Err(ErrorKind::ConfigTokenCommandFailed(name.to_string(),
+                                                       format!("{}", output.status))
+                           .into());
+    }
+
+    let stdout = try!(::std::str::from_utf8(&output.stdout).map_err(|_| {
+        Error::from(ErrorKind::ConfigTokenCommandFailed(name.to_string(),
+                                                        String::from("output is not utf8")))
+    }));
+    match stdout.lines().next().map(|l| l.trim()) {
+        Some(token) if token.len() > 0 => Ok(token.to_string()),
+        _ => {
+            Err(ErrorKind::ConfigTokenCommandFailed(name.to_string(),
+                                                    String::from("no output"))
+                    .into())
+        }
+    }
+}
+
+/// the name telegram uses for the given parse mode, `markdown` or `html`;
+/// None for plain text
+pub fn api_parse_mode(parse_mode: &str) -> Option<String> {
+    match parse_mode {
+        "markdown" => Some(String::from("Markdown")),
+        "html" => Some(String::from("HTML")),
+        _ => None,
+    }
+}
+
+/// masks the secret part of a token, so it can be shown or printed;
+/// the part before the colon is the bot id and thus kept
+pub fn mask_token(token: &str) -> String {
+    match token.find(':') {
+        Some(i) => format!("{}:****", &token[..i]),
+        None => String::from("****"),
+    }
+}
+
+/// the layout of the config file
+#[derive(Serialize, Deserialize, Default)]
+struct ConfigFile {
+    connections: Vec<Connection>,
+
+    /// the connection used if none is given on the command line
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default: Option<String>,
+}
+
+/// the environment variable the passphrase for encrypted configs is read from;
+/// if not set the passphrase is prompted for
+pub const PASSPHRASE_ENV: &'static str = "TELEECHO_PASSPHRASE";
+
+/// makes files created with the given options only readable by the user,
+/// as the config file contains tokens
+#[cfg(unix)]
+pub fn restrict_permissions(options: &mut OpenOptions) {
+    use std::os::unix::fs::OpenOptionsExt;
+    options.mode(0o600);
+}
+
+#[cfg(not(unix))]
+pub fn restrict_permissions(_: &mut OpenOptions) {}
+
+/// creates a new temporary file next to the given path, with a name no
+/// other save uses at the same time
+fn create_temp_file(path: &Path) -> Result<(PathBuf, File)> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    restrict_permissions(&mut options);
+
+    let mut attempt = 0;
+    loop {
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(format!(".{}.{}.tmp", ::std::process::id(), attempt));
+        let tmp_path = PathBuf::from(tmp_path);
+
+        match options.open(&tmp_path) {
+            Ok(file) => return Ok((tmp_path, file)),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists && attempt < 100 => {
+                attempt += 1
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// returns the passphrase from the environment, if it is set;
+/// an empty passphrase is rejected
+pub fn passphrase_from_env() -> Result<Option<String>> {
+    match ::std::env::var(PASSPHRASE_ENV) {
+        Ok(ref p) if p.len() == 0 => Err(ErrorKind::PassphraseEmpty.into()),
+        Ok(p) => Ok(Some(p)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// returns the passphrase from the environment or prompts for it.
+/// if `confirm` is set, the user has to type a prompted passphrase twice.
+/// an empty passphrase is rejected either way
+pub fn read_passphrase(confirm: bool) -> Result<String> {
+    if let Some(passphrase) = try!(passphrase_from_env()) {
+        return Ok(passphrase);
+    }
+
+    let passphrase = try!(rpassword::prompt_password_stderr("config passphrase: "));
+    if confirm {
+        let repeated = try!(rpassword::prompt_password_stderr("repeat passphrase: "));
+        if repeated != passphrase {
+            return Err(ErrorKind::PassphraseMismatch.into());
+        }
+    }
+
+    if passphrase.len() == 0 {
+        return Err(ErrorKind::PassphraseEmpty.into());
+    }
+
+    Ok(passphrase)
+}
+
+/// checks the name of a connection; whitespace is not allowed and replaced
+/// by dashes, and the name must not be empty
+pub fn connection_name(name: &str) -> Result<String> {
+    let name_without_whitespace = name.split_whitespace().collect::<Vec<&str>>().join("-");
+
+    if name_without_whitespace.len() == 0 {
+        return Err(ErrorKind::ConfigEmptyName.into());
+    }
+
+    Ok(name_without_whitespace)
+}
 
 pub struct Config {
-    /// the entries are name, bot token, user id
-    entries: Vec<(String, String, i64)>,
+    entries: Vec<Connection>,
+
+    /// name of the default connection, if set
+    default: Option<String>,
+
+    /// if the config file is encrypted this is the passphrase,
+    /// as it is needed again to write the file back
+    passphrase: Option<String>,
 }
 
 impl Config {
-    /// given a file this reads the content and tries to parse it into a Config object
+    /// the config file used if none is given, `~/.teleecho.conf`;
+    /// None if the home directory is unknown
+    pub fn default_path() -> Option<::std::path::PathBuf> {
+        ::std::env::home_dir().map(|mut path| {
+            path.push(".teleecho.conf");
+            path
+        })
+    }
+
+    /// reads the config file at the given path without changing it,
+    /// e.g. to look up a connection with `find`
+    pub fn load(path: &::std::path::Path) -> Result<Config> {
+        Config::parse(&mut try!(File::open(path)))
+    }
+
+    /// given a file this reads the content and tries to parse it into a Config object.
+    /// if the file is encrypted, the passphrase is taken from the environment
+    /// or prompted for
     pub fn parse(file: &mut File) -> Result<Config> {
 
         let mut content = String::new();
@@ -18,111 +583,554 @@ impl Config {
         try!(file.read_to_string(&mut content));
 
         // if file was created, there is nothing to read, so create an empty config object
-        if content.len() == 0 {
-            Ok(Config { entries: vec![] })
+        if content.trim().len() == 0 {
+            return Ok(Config {
+                entries: vec![],
+                default: None,
+                passphrase: None,
+            });
         }
-        // otherwise try to parse the file content into a configuration
-        else {
-            Ok(Config { entries: try!(serde_json::from_str(&content)) })
+
+        if envelope::is_encrypted(&content) {
+            let passphrase = try!(read_passphrase(false));
+            let plain = try!(envelope::decrypt(&content, &passphrase));
+            let file = try!(Config::parse_plain(&plain));
+            return Ok(Config {
+                entries: file.connections,
+                default: file.default,
+                passphrase: Some(passphrase),
+            });
         }
+
+        let file = try!(Config::parse_plain(&content));
+        Ok(Config {
+            entries: file.connections,
+            default: file.default,
+            passphrase: None,
+        })
     }
 
-    /// converts the config object into a string, that can be written to a file
+    /// parses the plain text config content
+    fn parse_plain(content: &str) -> Result<ConfigFile> {
+        match serde_json::from_str::<ConfigFile>(content) {
+            Ok(mut c) => {
+                for connection in &mut c.connections {
+                    connection.migrate();
+                }
+                Ok(c)
+            }
+            Err(e) => {
+                // older versions stored a plain list of (name, token, user id)
+                match serde_json::from_str::<Vec<(String, String, i64)>>(content) {
+                    Ok(old) => {
+                        Ok(ConfigFile {
+                            connections: old.into_iter()
+                                .map(|(name, token, chat_id)| {
+                                    Connection::new(name,
+                                                    Some(token),
+                                                    None,
+                                                    vec![ChatTarget::new(chat_id, None)])
+                                })
+                                .collect(),
+                            default: None,
+                        })
+                    }
+                    Err(_) => Err(ErrorKind::ConfigInvalid(format!("{}", e)).into()),
+                }
+            }
+        }
+    }
+
+    /// converts the config object into a string, that can be written to a file;
+    /// if a passphrase is set, the string is the encrypted envelope
     fn to_string(&self) -> Result<String> {
-        Ok(try!(serde_json::to_string(&self.entries)))
+        let file = ConfigFile {
+            connections: self.entries.clone(),
+            default: self.default.clone(),
+        };
+        let plain = try!(serde_json::to_string_pretty(&file));
+
+        match self.passphrase {
+            Some(ref passphrase) => envelope::encrypt(&plain, passphrase),
+            None => Ok(plain),
+        }
+    }
+
+    /// true if the config is written encrypted
+    pub fn is_encrypted(&self) -> bool {
+        self.passphrase.is_some()
+    }
+
+    /// sets the passphrase the config is encrypted with on the next save;
+    /// None stores the config as plain text again
+    pub fn set_passphrase(&mut self, passphrase: Option<String>) {
+        self.passphrase = passphrase;
     }
 
-    /// given a name, bot token and user id this tries to store this in the internal
-    /// list. 
+    /// given a connection this tries to store it in the internal list.
     /// this may fail if the same name already exists
-    pub fn add_entry(&mut self, name: String, token: String, user_id: i64) -> Result<()> {
-        for &(ref n, _, _) in &self.entries {
-            if n == &name {
-                return Err("config entry already exists".into());
-            }
+    pub fn add_entry(&mut self, connection: Connection) -> Result<()> {
+        if self.contains(&connection.name) {
+            return Err(ErrorKind::ConfigConnectionExists(connection.name).into());
         }
 
-        self.entries.push((name, token, user_id));
+        self.entries.push(connection);
         Ok(())
     }
 
-    /// given a file this 
-    pub fn save_to(&self, file: &mut File) -> Result<()> {
+    /// serializes the given connections, or all if none are given, so they
+    /// can be imported on another machine.
+    /// if a passphrase is given the export is encrypted like a config file
+    pub fn export(&self, names: &[&str], passphrase: Option<&str>) -> Result<String> {
+        let mut connections = vec![];
+        if names.len() == 0 {
+            connections = self.entries.clone();
+        } else {
+            for name in names {
+                connections.push(try!(self.find(Some(*name))).clone());
+            }
+        }
 
-        // get to the first position of the file to override everything
-        try!(file.seek(::std::io::SeekFrom::Start(0)));
+        let file = ConfigFile {
+            connections: connections,
+            default: None,
+        };
+        let plain = try!(serde_json::to_string_pretty(&file));
 
-        let to_write = try!(self.to_string());
-        let to_write_bytes = to_write.as_bytes();
+        match passphrase {
+            Some(passphrase) => envelope::encrypt(&plain, passphrase),
+            None => Ok(plain),
+        }
+    }
 
-        // write the config to file
-        try!(file.write_all(to_write_bytes));
+    /// parses connections written by `export`;
+    /// plain config files are accepted too.
+    /// if encrypted the passphrase is taken from the environment, or prompted for
+    /// if `may_prompt` is set; it is not if the export was read from stdin
+    pub fn parse_export(content: &str, may_prompt: bool) -> Result<Vec<Connection>> {
+        if envelope::is_encrypted(content) {
+            let passphrase = if may_prompt {
+                try!(read_passphrase(false))
+            } else {
+                try!(try!(passphrase_from_env())
+                    .ok_or(Error::from(ErrorKind::PassphraseNotInEnv)))
+            };
+            let plain = try!(envelope::decrypt(content, &passphrase));
+            Ok(try!(Config::parse_plain(&plain)).connections)
+        } else {
+            Ok(try!(Config::parse_plain(content)).connections)
+        }
+    }
 
-        // then trim the file to the size of the written.
-        // this is neccessary as when new config file size is
-        // smaller than before, the rest would remain and mess up everything
-        try!(file.set_len(to_write_bytes.len() as u64));
+    /// replaces the connection with the same name by the given one;
+    /// if there is none, the connection is added
+    pub fn replace_entry(&mut self, connection: Connection) {
+        match self.entries.iter().position(|c| c.name == connection.name) {
+            Some(index) => self.entries[index] = connection,
+            None => self.entries.push(connection),
+        }
+    }
+
+    /// writes the config to the file at the given path. it is written to a
+    /// temporary file next to it first, which then replaces the file, so an
+    /// interruption never leaves a truncated config file behind
+    pub fn save(&self, path: &Path) -> Result<()> {
+        self.save_atomically(path).map_err(|e| {
+            ErrorKind::ConfigWriteFailed(path.display().to_string(), format!("{}", e)).into()
+        })
+    }
+
+    fn save_atomically(&self, path: &Path) -> Result<()> {
+        // replace the file a symlinked config points to, not the symlink
+        let path = match fs::canonicalize(path) {
+            Ok(path) => path,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => path.to_path_buf(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let (tmp_path, mut file) = try!(create_temp_file(&path));
+        let written = file.write_all(try!(self.to_string()).as_bytes())
+            .and_then(|_| file.sync_all())
+            .and_then(|_| fs::rename(&tmp_path, &path));
+        if let Err(e) = written {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e.into());
+        }
+        Ok(())
+    }
 
+    /// checks that the config file is not readable by other users.
+    /// if it is world readable and contains plain tokens this is an error,
+    /// if it is only group readable a warning is printed
+    #[cfg(unix)]
+    pub fn check_permissions(&self, file: &File, path: &::std::path::Path) -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = try!(file.metadata()).permissions().mode();
+        let has_literal_tokens = !self.is_encrypted() &&
+                                 self.entries.iter().any(|c| c.has_literal_token());
+
+        if mode & 0o004 != 0 && has_literal_tokens {
+            Err(ErrorKind::ConfigInsecurePermissions(path.display().to_string()).into())
+        } else if mode & 0o044 != 0 {
+            warn(&format!("config file {} is readable by other users; consider `chmod 600 {}`",
+                          path.display(),
+                          path.display()));
+            Ok(())
+        } else {
+            Ok(())
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn check_permissions(&self, _: &File, _: &::std::path::Path) -> Result<()> {
         Ok(())
     }
 
-    /// given a connection this returns the token and id for the given
+    /// given a connection this returns the token and chat ids for the given
     /// connection, Error if non existent
     ///
-    /// given no connection this returns the token and id if there is only one
-    /// connection registered, Error otherwise
-    pub fn get(&self, connection: Option<&str>) -> Result<(String, i64)> {
+    /// given no connection this returns the token and chat ids of the default connection,
+    /// or if none is set and there is only one connection registered, of this one.
+    /// Error otherwise
+    ///
+    /// the token is resolved, so token commands and environment references
+    /// are already evaluated
+    pub fn get(&self, connection: Option<&str>) -> Result<(String, Vec<i64>)> {
+        let c = try!(self.find(connection));
+        Ok((try!(c.resolve_token()), c.chat_ids()))
+    }
+
+    /// like `get` but returns the stored connection without resolving the token
+    pub fn find(&self, connection: Option<&str>) -> Result<&Connection> {
         match connection {
             Some(con) => {
-                for &(ref n, ref t, ref i) in &self.entries {
-                    if n == con {
-                        return Ok((t.clone(), i.clone()));
+                for c in &self.entries {
+                    if c.name == con {
+                        return Ok(c);
                     }
                 }
                 Err(ErrorKind::ConfigConnectionNotExist.into())
             }
             None => {
+                if let Some(ref default) = self.default {
+                    return self.find(Some(default.as_str()));
+                }
+
                 if self.entries.len() == 1 {
-                    let (_, ref t, ref i) = self.entries[0];
-                    Ok((t.clone(), i.clone()))
+                    Ok(&self.entries[0])
                 } else {
-                    Err(format!("as no connection was given, the default would be used, but \
-                                 there does not exist one, but {} connections to choose from",
-                                self.entries.len())
-                            .into())
+                    let names = self.entries
+                        .iter()
+                        .map(|c| c.name.as_str())
+                        .collect::<Vec<&str>>()
+                        .join(", ");
+                    Err(ErrorKind::ConfigNoDefault(names).into())
+                }
+            }
+        }
+    }
+
+    /// all registered connections
+    pub fn connections(&self) -> &[Connection] {
+        &self.entries
+    }
+
+    /// true if a connection with the given name exists
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.iter().any(|c| c.name == name)
+    }
+
+    /// returns the connection with the given name for modification
+    fn find_mut(&mut self, name: &str) -> Result<&mut Connection> {
+        match self.entries.iter_mut().find(|c| c.name == name) {
+            Some(c) => Ok(c),
+            None => Err(ErrorKind::ConfigConnectionNotExist.into()),
+        }
+    }
+
+    /// renames the given connection; the default follows the rename.
+    /// this fails if the new name is already taken
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        if self.contains(to) {
+            return Err(ErrorKind::ConfigConnectionExists(to.to_string()).into());
+        }
+
+        try!(self.find_mut(from)).name = String::from(to);
+
+        if self.default() == Some(from) {
+            self.default = Some(String::from(to));
+        }
+        Ok(())
+    }
+
+    /// copies the given connection with all its options to a new name.
+    /// this fails if the new name is already taken
+    pub fn copy(&mut self, from: &str, to: &str) -> Result<()> {
+        let mut copy = try!(self.find(Some(from))).clone();
+        copy.name = String::from(to);
+        self.add_entry(copy)
+    }
+
+    /// sets a single option of the given connection, see `Connection::set`
+    pub fn set(&mut self, name: &str, key: &str, value: &str) -> Result<()> {
+        let connection = try!(self.find_mut(name));
+
+        // only apply the change if the whole connection stays valid
+        let mut changed = connection.clone();
+        try!(changed.set(key, value));
+        *connection = changed;
+        Ok(())
+    }
+
+    /// prints all settings of the given connection; the token is masked
+    pub fn show(&self, name: Option<&str>) -> Result<()> {
+        let c = try!(self.find(name));
+
+        println!("name:                 {}{}",
+                 c.name,
+                 if self.default() == Some(c.name.as_str()) { " (default)" } else { "" });
+        println!("token:                {}", c.token_source());
+        for chat in &c.chats {
+            match chat.thread_id {
+                Some(thread_id) => {
+                    println!("chat:                 {} ({}), topic {}",
+                             chat.id,
+                             chat.chat_type.as_ref().map(|t| t.as_str()).unwrap_or("?"),
+                             thread_id)
+                }
+                None => {
+                    println!("chat:                 {} ({})",
+                             chat.id,
+                             chat.chat_type.as_ref().map(|t| t.as_str()).unwrap_or("?"))
                 }
             }
         }
+        for (name, id) in &c.topics {
+            println!("topic:{:<15} {}", name, id);
+        }
+        println!("parse_mode:           {}",
+                 c.parse_mode.as_ref().map(|p| p.as_str()).unwrap_or("none"));
+        println!("disable_link_preview: {}",
+                 c.disable_link_preview.map(|d| d.to_string()).unwrap_or(String::from("none")));
+        Ok(())
+    }
+
+    /// adds another chat to the given connection
+    pub fn add_chat(&mut self, name: &str, chat: ChatTarget) -> Result<()> {
+        try!(self.find_mut(name)).add_chat(chat)
+    }
+
+    /// removes a chat from the given connection
+    pub fn remove_chat(&mut self, name: &str, id: i64) -> Result<()> {
+        try!(self.find_mut(name)).remove_chat(id)
     }
 
-    /// prints out a list of all contained connections on the command line
+    /// replaces the id of a group that was upgraded to a supergroup
+    /// in all connections; returns whether any connection sent to it
+    pub fn migrate_chat(&mut self, from: i64, to: i64) -> bool {
+        let mut changed = false;
+        for connection in &mut self.entries {
+            for chat in connection.chats.iter_mut().filter(|c| c.id == from) {
+                chat.id = to;
+                chat.chat_type = Some(String::from("supergroup"));
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// sets the given connection as default;
+    /// this may fail if the given connection is not in the list
+    pub fn set_default(&mut self, name: &str) -> Result<()> {
+        try!(self.find(Some(name)));
+        self.default = Some(String::from(name));
+        Ok(())
+    }
+
+    /// returns the name of the default connection, if set
+    pub fn default(&self) -> Option<&str> {
+        self.default.as_ref().map(|d| d.as_str())
+    }
+
+    /// prints out a list of all contained connections on the command line;
+    /// the default connection is marked with a star
     pub fn list(&self) {
-        for &(ref n, _, _) in &self.entries {
-            println!("{}", n);
+        for c in &self.entries {
+            if self.default() == Some(c.name.as_str()) {
+                println!("* {}", c.name);
+            } else {
+                println!("  {}", c.name);
+            }
         }
     }
 
-    /// tries to remove the given connection; 
+    /// prints out all connections with the bot, chat and options;
+    /// information that is not cached is shown as `?`
+    pub fn list_long(&self) {
+        println!("  {:<16} {:<24} {:>16} {:<10} {}",
+                 "NAME",
+                 "BOT",
+                 "CHAT",
+                 "TYPE",
+                 "OPTIONS");
+        for c in &self.entries {
+            let marker = if self.default() == Some(c.name.as_str()) { "*" } else { " " };
+            let bot = c.bot
+                .as_ref()
+                .map(|b| format!("@{}", b.username))
+                .unwrap_or(String::from("?"));
+
+            let mut options = vec![];
+            if let Some(ref p) = c.parse_mode {
+                options.push(format!("parse_mode={}", p));
+            }
+            if let Some(d) = c.disable_link_preview {
+                options.push(format!("disable_link_preview={}", d));
+            }
+
+            // every further chat of a connection gets its own line
+            for (i, chat) in c.chats.iter().enumerate() {
+                let chat_type = chat.chat_type.as_ref().map(|t| t.as_str()).unwrap_or("?");
+                if i == 0 {
+                    println!("{} {:<16} {:<24} {:>16} {:<10} {}",
+                             marker,
+                             c.name,
+                             bot,
+                             chat.id,
+                             chat_type,
+                             options.join(" "));
+                } else {
+                    println!("  {:<16} {:<24} {:>16} {:<10}", "", "", chat.id, chat_type);
+                }
+            }
+        }
+    }
+
+    fn list_entry<'a>(&self, c: &'a Connection) -> ListEntry<'a> {
+        ListEntry {
+            name: &c.name,
+            default: self.default() == Some(c.name.as_str()),
+            bot_id: c.bot.as_ref().map(|b| b.id),
+            bot_username: c.bot.as_ref().map(|b| b.username.as_str()),
+            chats: &c.chats,
+            topics: &c.topics,
+            parse_mode: c.parse_mode.as_ref().map(|p| p.as_str()),
+            disable_link_preview: c.disable_link_preview,
+        }
+    }
+
+    /// returns all connections as json for scripts; tokens are left out
+    pub fn list_json(&self) -> Result<String> {
+        let entries = self.entries
+            .iter()
+            .map(|c| self.list_entry(c))
+            .collect::<Vec<ListEntry>>();
+
+        Ok(try!(serde_json::to_string_pretty(&entries)))
+    }
+
+    /// returns the given connection as json like `list_json` does
+    pub fn connection_json(&self, name: &str) -> Result<String> {
+        let c = try!(self.find(Some(name)));
+        Ok(try!(serde_json::to_string_pretty(&self.list_entry(c))))
+    }
+
+    /// stores the bot and the types of the chats of the given connection;
+    /// `chat_types` holds chat id and type
+    pub fn set_cached_info(&mut self,
+                           name: &str,
+                           bot: Option<BotInfo>,
+                           chat_types: Vec<(i64, String)>)
+                           -> Result<()> {
+        let connection = try!(self.find_mut(name));
+        if bot.is_some() {
+            connection.bot = bot;
+        }
+        for (id, chat_type) in chat_types {
+            for chat in connection.chats.iter_mut().filter(|c| c.id == id) {
+                chat.chat_type = Some(chat_type.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// tries to remove the given connection;
     /// this may fail if the given connection is not in the list
     pub fn remove(&mut self, to_remove: &str) -> Result<()> {
 
         // get the index of the one to remove
-        let mut to_remove_index = None;
-        let mut current_index = 0;
-        for &(ref n, _, _) in &self.entries {
-            if n == to_remove {
-                to_remove_index = Some(current_index);
-                break;
+        match self.entries.iter().position(|c| c.name == to_remove) {
+            Some(index) => {
+                self.entries.remove(index);
+
+                // the default must not point to a removed connection
+                if self.default() == Some(to_remove) {
+                    self.default = None;
+                }
+                Ok(())
             }
-            current_index += 1;
+            None => Err(ErrorKind::ConfigConnectionNotExist.into()),
         }
+    }
+}
 
-        // if was found, remove, otherwise return error
-        if to_remove_index.is_some() {
-            self.entries.remove(to_remove_index.unwrap());
-            Ok(())
-        } else {
-            Err(ErrorKind::ConfigConnectionNotExist.into())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn config() -> Config {
+        Config {
+            entries: vec![Connection::new(String::from("backup"),
+                                          Some(String::from("123456:ABC-DEF1234ghIkl")),
+                                          None,
+                                          vec![ChatTarget::new(12345678, None)]),
+                          Connection::new(String::from("deploy"),
+                                          Some(String::from("654321:ABC-DEF1234ghIkl")),
+                                          None,
+                                          vec![ChatTarget::new(-100123, None)])],
+            default: Some(String::from("backup")),
+            passphrase: None,
+        }
+    }
+
+    #[test]
+    fn plain_export_round_trip() {
+        let export = config().export(&["deploy"], None).unwrap();
+        let connections = Config::parse_export(&export, false).unwrap();
+        assert_eq!(connections.len(), 1);
+        assert_eq!(connections[0].name, "deploy");
+        assert_eq!(connections[0].chat_ids(), vec![-100123]);
+    }
+
+    #[test]
+    fn plain_config_can_be_imported() {
+        let content = config().to_string().unwrap();
+        let connections = Config::parse_export(&content, false).unwrap();
+        assert_eq!(connections.iter().map(|c| c.name.as_str()).collect::<Vec<&str>>(),
+                   vec!["backup", "deploy"]);
+    }
+
+    // the only test using the environment, as tests run in parallel
+    #[test]
+    fn encrypted_export_needs_the_passphrase_in_the_environment() {
+        let export = config().export(&[], Some("secret")).unwrap();
+
+        env::remove_var(PASSPHRASE_ENV);
+        match Config::parse_export(&export, false) {
+            Err(Error(ErrorKind::PassphraseNotInEnv, _)) => {}
+            other => panic!("expected PassphraseNotInEnv, got {:?}", other.map(|c| c.len())),
         }
+
+        env::set_var(PASSPHRASE_ENV, "secret");
+        let connections = Config::parse_export(&export, false);
+        env::remove_var(PASSPHRASE_ENV);
+        assert_eq!(connections.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn garbage_is_not_an_export() {
+        assert!(Config::parse_export("not json", false).is_err());
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,128 @@
+use std::fs::File;
+use std::path::Path;
+
+use teleecho::botapi::{self, BotApi};
+use teleecho::config::{Config, Connection};
+use teleecho::error::*;
+use teleecho::teleecho::SendOptions;
+
+/// the text of the message sent to check a connection
+const TEST_MESSAGE: &'static str = "teleecho doctor: this connection works";
+
+/// prints the result of a single check and returns whether it passed.
+/// failed checks of a connection come with an explanation, if there is one
+fn report<T>(what: &str, result: &Result<T>, connection: Option<&str>) -> bool {
+    match *result {
+        Ok(_) => {
+            println!("  ok    {}", what);
+            true
+        }
+        Err(ref e) => {
+            println!("  FAIL  {}: {}", what, e);
+            if let Some(explanation) = connection.and_then(|c| botapi::explain(e, c)) {
+                println!("        {}", explanation);
+            }
+            false
+        }
+    }
+}
+
+/// checks the permissions of the config file
+#[cfg(unix)]
+fn check_permissions(file: &File) -> Result<String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = try!(file.metadata()).permissions().mode() & 0o777;
+    if mode & 0o077 != 0 {
+        Err(ErrorKind::ConfigPermissionsTooOpen(mode).into())
+    } else {
+        Ok(format!("{:o}", mode))
+    }
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_: &File) -> Result<String> {
+    Ok(String::from("not checked"))
+}
+
+/// checks a single connection: resolves the token, asks telegram about the bot
+/// and sends a silent test message to every chat
+fn check_connection(connection: &Connection) -> bool {
+    println!("connection {}", connection.name);
+
+    let token = connection.resolve_token();
+    if !report("token resolved", &token, Some(connection.name.as_str())) {
+        return false;
+    }
+    let token = token.unwrap();
+
+    let api = BotApi::new(&token);
+
+    let me = api.get_me();
+    let what = match me {
+        Ok(ref me) => {
+            format!("token accepted by telegram, bot is @{}",
+                    me.find("username").and_then(|u| u.as_str()).unwrap_or("?"))
+        }
+        Err(_) => String::from("token accepted by telegram"),
+    };
+    if !report(&what, &me, Some(connection.name.as_str())) {
+        return false;
+    }
+
+    let options = SendOptions { silent: true, ..SendOptions::default() };
+
+    let mut success = true;
+    for recipient in connection.recipients(None) {
+        let sent = api.send_text(recipient, TEST_MESSAGE, &options);
+        success &= report(&format!("silent test message sent to chat {}", recipient.chat_id),
+                          &sent,
+                          Some(connection.name.as_str()));
+    }
+    success
+}
+
+/// checks the config file at the given path and the given connection,
+/// or all connections if none is given, and prints the results.
+/// the file is only read, and not created if it is missing.
+/// returns an error if any check failed
+pub fn run(path: &Path, connection: Option<&str>) -> Result<()> {
+    println!("config file {}", path.display());
+
+    let file = File::open(path);
+    if !report("exists and is readable", &file, None) {
+        return Err(ErrorKind::DoctorFailed.into());
+    }
+    let mut file = file.unwrap();
+
+    let config = Config::parse(&mut file);
+    let what = match config {
+        Ok(ref c) => format!("parses, {} connections", c.connections().len()),
+        Err(_) => String::from("parses"),
+    };
+    let mut success = report(&what, &config, None);
+    success &= report("permissions", &check_permissions(&file), None);
+
+    let config = match config {
+        Ok(c) => c,
+        Err(_) => return Err(ErrorKind::DoctorFailed.into()),
+    };
+
+    match connection {
+        Some(_) => {
+            let c = try!(config.find(connection));
+            success &= check_connection(c);
+        }
+        None => {
+            for c in config.connections() {
+                success &= check_connection(c);
+            }
+        }
+    }
+
+    if success {
+        Ok(())
+    } else {
+        Err(ErrorKind::DoctorFailed.into())
+    }
+}
@@ -0,0 +1,163 @@
+extern crate crypto;
+extern crate base64;
+extern crate rand;
+extern crate serde_json;
+
+use self::crypto::aead::{AeadEncryptor, AeadDecryptor};
+use self::crypto::chacha20poly1305::ChaCha20Poly1305;
+use self::crypto::scrypt::{scrypt, ScryptParams};
+use self::rand::{OsRng, Rng};
+use teleecho::error::*;
+
+/// the marker that identifies an encrypted config file
+const ENVELOPE_VERSION: u32 = 1;
+
+/// scrypt parameters for new envelopes: N = 2^15, r = 8, p = 1
+/// which needs 32 MiB of memory per derivation
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+/// an authenticated encrypted config file.
+/// the content is encrypted with ChaCha20-Poly1305, the key is derived
+/// from the passphrase with scrypt; all binary values are base64 encoded
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    teleecho_encrypted: u32,
+    log_n: u8,
+    r: u32,
+    p: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+    tag: String,
+}
+
+/// true if the given file content is an encrypted envelope
+pub fn is_encrypted(content: &str) -> bool {
+    serde_json::from_str::<Envelope>(content).is_ok()
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], log_n: u8, r: u32, p: u32) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    scrypt(passphrase.as_bytes(),
+           salt,
+           &ScryptParams::new(log_n, r, p),
+           &mut key);
+    key
+}
+
+fn decode(field: &str, value: &str) -> Result<Vec<u8>> {
+    base64::decode(value)
+        .map_err(|_| ErrorKind::ConfigEnvelopeInvalid(format!("{} is not valid base64", field)).into())
+}
+
+/// encrypts the given plain text with a key derived from the passphrase
+/// and returns the serialized envelope
+pub fn encrypt(plain: &str, passphrase: &str) -> Result<String> {
+    let mut rng = try!(OsRng::new());
+
+    // a new salt for every write gives a new key, so the short
+    // nonce is never used twice with the same key
+    let mut salt = [0u8; 16];
+    let mut nonce = [0u8; 8];
+    rng.fill_bytes(&mut salt);
+    rng.fill_bytes(&mut nonce);
+
+    let key = derive_key(passphrase, &salt, SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P);
+
+    let mut ciphertext = vec![0u8; plain.len()];
+    let mut tag = [0u8; 16];
+    ChaCha20Poly1305::new(&key, &nonce, &[]).encrypt(plain.as_bytes(), &mut ciphertext, &mut tag);
+
+    let envelope = Envelope {
+        teleecho_encrypted: ENVELOPE_VERSION,
+        log_n: SCRYPT_LOG_N,
+        r: SCRYPT_R,
+        p: SCRYPT_P,
+        salt: base64::encode(&salt),
+        nonce: base64::encode(&nonce),
+        ciphertext: base64::encode(&ciphertext),
+        tag: base64::encode(&tag),
+    };
+
+    Ok(try!(serde_json::to_string_pretty(&envelope)))
+}
+
+/// given the serialized envelope this derives the key from the passphrase,
+/// checks the authentication tag and returns the plain text
+pub fn decrypt(content: &str, passphrase: &str) -> Result<String> {
+    let envelope: Envelope = try!(serde_json::from_str(content)
+        .map_err(|e| ErrorKind::ConfigEnvelopeInvalid(format!("{}", e))));
+
+    if envelope.teleecho_encrypted != ENVELOPE_VERSION {
+        return Err(ErrorKind::ConfigEnvelopeInvalid(format!("unknown version {}",
+                                                            envelope.teleecho_encrypted))
+                           .into());
+    }
+
+    let salt = try!(decode("salt", &envelope.salt));
+    let nonce = try!(decode("nonce", &envelope.nonce));
+    let ciphertext = try!(decode("ciphertext", &envelope.ciphertext));
+    let tag = try!(decode("tag", &envelope.tag));
+
+    // scrypt panics on invalid parameters and a large N takes all memory,
+    // so only the parameters written by `encrypt` are accepted
+    if (envelope.log_n, envelope.r, envelope.p) != (SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P) {
+        return Err(ErrorKind::ConfigEnvelopeInvalid(String::from("unsupported scrypt parameters"))
+                       .into());
+    }
+
+    if nonce.len() != 8 || tag.len() != 16 {
+        return Err(ErrorKind::ConfigEnvelopeInvalid(String::from("wrong nonce or tag size"))
+                       .into());
+    }
+
+    let key = derive_key(passphrase, &salt, envelope.log_n, envelope.r, envelope.p);
+
+    let mut plain = vec![0u8; ciphertext.len()];
+    if !ChaCha20Poly1305::new(&key, &nonce, &[]).decrypt(&ciphertext, &mut plain, &tag) {
+        return Err(ErrorKind::ConfigDecryptFailed.into());
+    }
+
+    Ok(try!(String::from_utf8(plain)
+                .map_err(|_| ErrorKind::ConfigEnvelopeInvalid(String::from("content is not utf8")))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let envelope = encrypt("{\"connections\":[]}", "secret").unwrap();
+        assert!(is_encrypted(&envelope));
+        assert_eq!(decrypt(&envelope, "secret").unwrap(), "{\"connections\":[]}");
+    }
+
+    #[test]
+    fn wrong_passphrase() {
+        let envelope = encrypt("plain", "secret").unwrap();
+        match decrypt(&envelope, "guess") {
+            Err(Error(ErrorKind::ConfigDecryptFailed, _)) => {}
+            other => panic!("expected ConfigDecryptFailed, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unsupported_scrypt_parameters() {
+        let envelope = encrypt("plain", "secret")
+            .unwrap()
+            .replace(&format!("\"log_n\": {}", SCRYPT_LOG_N), "\"log_n\": 40");
+        assert!(envelope.contains("\"log_n\": 40"));
+        match decrypt(&envelope, "secret") {
+            Err(Error(ErrorKind::ConfigEnvelopeInvalid(_), _)) => {}
+            other => panic!("expected ConfigEnvelopeInvalid, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn plain_config_is_not_encrypted() {
+        assert!(!is_encrypted("{\"connections\":[]}"));
+    }
+}
@@ -1,18 +1,383 @@
-extern crate telegram_bot;
 extern crate serde_json;
 
+use std::io::{self, Write};
+use std::sync::atomic::{ATOMIC_BOOL_INIT, AtomicBool, Ordering};
+use teleecho::botapi;
+use teleecho::config::mask_token;
+
+/// exit status of the binary for errors without a more specific status
+pub const EXIT_FAILURE: i32 = 1;
+
+/// exit status if messages could not be delivered, because the bot
+/// was blocked or removed or the chat was deleted
+pub const EXIT_DELIVERY_FAILED: i32 = 3;
+
+/// exit status if the config file or a connection in it is unusable
+pub const EXIT_CONFIG: i32 = 4;
+
+/// exit status if pairing a chat with a connection failed
+pub const EXIT_REGISTRATION: i32 = 5;
+
+/// exit status if telegram could not be reached or answered with an error
+pub const EXIT_NETWORK: i32 = 6;
+
+/// exit status if telegram rejected the token
+pub const EXIT_AUTH: i32 = 7;
+
+/// exit status if the command given to `teleecho run` could not be started
+pub const EXIT_CHILD: i32 = 8;
+
+/// set by `--quiet`; suppresses warnings
+static QUIET: AtomicBool = ATOMIC_BOOL_INIT;
+
 error_chain! {
     foreign_links{
         ::std::io::Error, Io;
         self::serde_json::Error, SerdeJson;
-        self::telegram_bot::Error, TelegramBot;
         ::std::str::Utf8Error, Utf8Error;
     }
 
     errors {
+        TelegramApi(code: i64, description: String, migrate_to_chat_id: Option<i64>) {
+            description("telegram api error")
+            display("telegram api error {}: {}", code, description)
+        }
+
+        InvalidTokenFormat {
+            description("invalid token format")
+            display("invalid token format; a token looks like 123456:ABC-DEF1234ghIkl")
+        }
+
+        Network(reason: String) {
+            description("network error")
+            display("network error: {}", reason)
+        }
+
+        PairingExpired {
+            description("pairing link expired")
+            display("pairing link expired; run `teleecho new` again for a new one")
+        }
+
+        PairingNotConfirmed {
+            description("pairing was not confirmed")
+            display("pairing was not confirmed; nothing was saved")
+        }
+
+        PairingNotChannelAdmin {
+            description("bot is not an administrator of the channel")
+            display("bot is not an administrator of the channel who may post messages; add it \
+                     as administrator first")
+        }
+
+        PairingNotAChannel(name: String) {
+            description("chat is not a channel")
+            display("{} is not a channel", name)
+        }
+
+        TelegramUnexpectedAnswer(reason: String) {
+            description("unexpected answer from telegram")
+            display("unexpected answer from telegram: {}", reason)
+        }
+
+        NoFilePath {
+            description("telegram returned no file path")
+            display("telegram returned no file path; the file may be larger than 20 MB")
+        }
+
+        DownloadFailed(status: String) {
+            description("could not download file")
+            display("could not download file: {}", status)
+        }
+
+        QrCode(reason: String) {
+            description("could not create qr code")
+            display("could not create qr code: {}", reason)
+        }
+
+        ChildFailed(command: String, reason: String) {
+            description("could not start command")
+            display("could not start {}: {}", command, reason)
+        }
+
+        UnsupportedPlatform(what: String) {
+            description("not supported on this platform")
+            display("{} is only supported on unix", what)
+        }
+
+        SignalHandler(reason: String) {
+            description("could not set Ctrl-C handler")
+            display("could not set Ctrl-C handler: {}", reason)
+        }
+
+        InvalidArgument(what: String, value: String) {
+            description("invalid argument")
+            display("invalid {}: {}", what, value)
+        }
+
+        ReplyToSeveralChats {
+            description("reply-to needs a connection with a single chat")
+            display("--reply-to needs a connection with a single chat, as message ids are \
+                     only unique within a chat")
+        }
+
+        InvalidDuration(value: String) {
+            description("invalid duration")
+            display("invalid duration {}; use e.g. 90, 30s, 10m or 2h", value)
+        }
+
+        AskNoChoices {
+            description("no choices given")
+            display("no choices given; use e.g. --choices yes,no")
+        }
+
+        AskTooManyChoices(max: usize) {
+            description("too many choices given")
+            display("too many choices given; at most {} are possible", max)
+        }
+
+        DoctorFailed {
+            description("some checks failed")
+            display("some checks failed")
+        }
+
         ConfigConnectionNotExist {
             description("specified connection does not exist")
             display("specified connection does not exist")
         }
+
+        ConfigConnectionExists(name: String) {
+            description("connection name already taken")
+            display("connection name already taken: {}", name)
+        }
+
+        ConfigUnknownKey(key: String) {
+            description("unknown connection option")
+            display("unknown connection option {}; known options are: {}",
+                    key,
+                    ::teleecho::config::CONNECTION_KEYS.join(", "))
+        }
+
+        ConfigUnknownTopic(connection: String, topic: String) {
+            description("unknown topic")
+            display("connection '{}' has no topic named {}; name it with `teleecho set {} \
+                     topic:{} <THREAD ID>`",
+                    connection,
+                    topic,
+                    connection,
+                    topic)
+        }
+
+        ConfigInvalidValue(key: String, value: String) {
+            description("invalid value for connection option")
+            display("invalid value for connection option {}: {}", key, value)
+        }
+
+        ConfigEmptyName {
+            description("connection name must not be empty")
+            display("connection name must not be empty")
+        }
+
+        ConfigChatExists(connection: String, chat: i64) {
+            description("connection already sends to chat")
+            display("connection {} already sends to chat {}", connection, chat)
+        }
+
+        ConfigChatNotFound(connection: String, chat: i64) {
+            description("connection does not send to chat")
+            display("connection {} does not send to chat {}", connection, chat)
+        }
+
+        ConfigLastChat {
+            description("the last chat of a connection can not be removed")
+            display("the last chat of a connection can not be removed")
+        }
+
+        ConfigNoDefault(available: String) {
+            description("no connection given and no default connection set")
+            display("no connection given and no default connection set; choose one of: {} \
+                     or set a default with `teleecho default <name>`",
+                    available)
+        }
+
+        ConfigNoToken(connection: String) {
+            description("connection has neither a token nor a token command")
+            display("connection '{}' has neither a token nor a token command", connection)
+        }
+
+        ConfigTokenEnvMissing(connection: String, var: String) {
+            description("environment variable referenced as token is not set")
+            display("connection '{}' references the environment variable {} as token, \
+                     but it is not set",
+                    connection,
+                    var)
+        }
+
+        ConfigTokenCommandFailed(connection: String, reason: String) {
+            description("token command failed")
+            display("token command of connection '{}' failed: {}", connection, reason)
+        }
+
+        ConfigInsecurePermissions(path: String) {
+            description("config file containing tokens is readable by other users")
+            display("config file {} contains bot tokens and is readable by other users; \
+                     run `chmod 600 {}` or use token_command instead",
+                    path,
+                    path)
+        }
+
+        ConfigPermissionsTooOpen(mode: u32) {
+            description("config file is readable by other users")
+            display("mode is {:o}, but should only be readable by you; run `chmod 600` on it",
+                    mode)
+        }
+
+        ConfigAlreadyEncrypted {
+            description("config file is already encrypted")
+            display("config file is already encrypted")
+        }
+
+        ConfigNotEncrypted {
+            description("config file is not encrypted")
+            display("config file is not encrypted")
+        }
+
+        ConfigInvalid(reason: String) {
+            description("config file is malformed")
+            display("config file is malformed: {}", reason)
+        }
+
+        ConfigWriteFailed(path: String, reason: String) {
+            description("could not write config file")
+            display("could not write config file {}: {}", path, reason)
+        }
+
+        ConfigEnvelopeInvalid(reason: String) {
+            description("encrypted config file is malformed")
+            display("encrypted config file is malformed: {}", reason)
+        }
+
+        ConfigDecryptFailed {
+            description("could not decrypt config file")
+            display("could not decrypt config file; wrong passphrase or the file was modified")
+        }
+
+        PassphraseMismatch {
+            description("passphrases do not match")
+            display("passphrases do not match")
+        }
+
+        PassphraseEmpty {
+            description("empty passphrase")
+            display("empty passphrase")
+        }
+
+        PassphraseNotInEnv {
+            description("passphrase can not be prompted for")
+            display("the passphrase can not be prompted for, as stdin is already used; set {}",
+                    ::teleecho::config::PASSPHRASE_ENV)
+        }
+    }
+}
+
+impl Error {
+    /// the status the binary exits with because of this error;
+    /// the statuses are listed in the readme
+    pub fn exit_code(&self) -> i32 {
+        match *self.kind() {
+            ErrorKind::InvalidTokenFormat => EXIT_AUTH,
+            ErrorKind::TelegramApi(..) if botapi::is_token_error(self) => EXIT_AUTH,
+            ErrorKind::TelegramApi(..) if botapi::is_fatal(self) => EXIT_DELIVERY_FAILED,
+            ErrorKind::TelegramApi(..) |
+            ErrorKind::TelegramUnexpectedAnswer(_) |
+            ErrorKind::NoFilePath |
+            ErrorKind::DownloadFailed(_) |
+            ErrorKind::Network(_) => EXIT_NETWORK,
+            // the config is parsed into its own kinds, so only answers
+            // of telegram that are no json are left
+            ErrorKind::SerdeJson(_) => EXIT_NETWORK,
+            ErrorKind::PairingExpired |
+            ErrorKind::PairingNotConfirmed |
+            ErrorKind::PairingNotChannelAdmin |
+            ErrorKind::PairingNotAChannel(_) |
+            ErrorKind::QrCode(_) => EXIT_REGISTRATION,
+            ErrorKind::ChildFailed(..) => EXIT_CHILD,
+            ErrorKind::ConfigConnectionNotExist |
+            ErrorKind::ConfigConnectionExists(_) |
+            ErrorKind::ConfigUnknownKey(_) |
+            ErrorKind::ConfigUnknownTopic(..) |
+            ErrorKind::ConfigInvalidValue(..) |
+            ErrorKind::ConfigEmptyName |
+            ErrorKind::ConfigChatExists(..) |
+            ErrorKind::ConfigChatNotFound(..) |
+            ErrorKind::ConfigLastChat |
+            ErrorKind::ConfigNoDefault(_) |
+            ErrorKind::ConfigNoToken(_) |
+            ErrorKind::ConfigTokenEnvMissing(..) |
+            ErrorKind::ConfigTokenCommandFailed(..) |
+            ErrorKind::ConfigInsecurePermissions(_) |
+            ErrorKind::ConfigPermissionsTooOpen(_) |
+            ErrorKind::ConfigAlreadyEncrypted |
+            ErrorKind::ConfigNotEncrypted |
+            ErrorKind::ConfigInvalid(_) |
+            ErrorKind::ConfigWriteFailed(..) |
+            ErrorKind::ConfigEnvelopeInvalid(_) |
+            ErrorKind::ConfigDecryptFailed |
+            ErrorKind::PassphraseMismatch |
+            ErrorKind::PassphraseEmpty |
+            ErrorKind::PassphraseNotInEnv => EXIT_CONFIG,
+            // a file to send or the terminal could not be read or written
+            ErrorKind::Io(_) |
+            ErrorKind::Utf8Error(_) |
+            ErrorKind::Msg(_) => EXIT_FAILURE,
+            // invalid arguments and the like
+            _ => EXIT_FAILURE,
+        }
+    }
+}
+
+/// makes sure the given token never ends up in an error message;
+/// if the message contains it, it is replaced by a masked version.
+/// the kind of the error is always kept, so is its exit status
+pub fn redact(err: Error, token: &str) -> Error {
+    if token.len() == 0 || !format!("{}", err).contains(token) {
+        return err;
+    }
+
+    let masked = mask_token(token);
+    let mask = |text: String| text.replace(token, &masked);
+    match err {
+        Error(ErrorKind::Network(reason), _) => ErrorKind::Network(mask(reason)).into(),
+        Error(ErrorKind::TelegramApi(code, description, migrate_to), _) => {
+            ErrorKind::TelegramApi(code, mask(description), migrate_to).into()
+        }
+        Error(ErrorKind::TelegramUnexpectedAnswer(reason), _) => {
+            ErrorKind::TelegramUnexpectedAnswer(mask(reason)).into()
+        }
+        Error(ErrorKind::DownloadFailed(status), _) => {
+            ErrorKind::DownloadFailed(mask(status)).into()
+        }
+        Error(ErrorKind::InvalidArgument(what, value), _) => {
+            ErrorKind::InvalidArgument(what, mask(value)).into()
+        }
+        Error(ErrorKind::Io(err), _) => {
+            ErrorKind::Io(io::Error::new(err.kind(), mask(format!("{}", err)))).into()
+        }
+        Error(ErrorKind::Msg(message), _) => ErrorKind::Msg(mask(message)).into(),
+        // the other kinds only carry text of the command line or the config,
+        // not of telegram or the network
+        err => err,
+    }
+}
+
+/// suppresses warnings from now on, e.g. for `--quiet`
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::SeqCst);
+}
+
+/// writes a warning about something that did not stop teleecho to stderr,
+/// unless warnings are suppressed
+pub fn warn(message: &str) {
+    if !QUIET.load(Ordering::SeqCst) {
+        let _ = writeln!(::std::io::stderr(), "warning: {}", message);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,77 @@
+extern crate serde_json;
+
+use self::serde_json::Value;
+use teleecho::botapi::{self, BotApi};
+use teleecho::error::*;
+
+/// the seconds a single long poll waits for updates
+pub const POLL_TIMEOUT: u32 = 30;
+
+/// long polls telegram for messages sent to the bot and keeps track of the
+/// offset, so every update is only received once
+pub struct Listener {
+    api: BotApi,
+    offset: i64,
+}
+
+impl Listener {
+    pub fn new(api: BotApi) -> Listener {
+        Listener {
+            api: api,
+            offset: 0,
+        }
+    }
+
+    /// the api the listener polls with, e.g. to answer messages
+    pub fn api(&self) -> &BotApi {
+        &self.api
+    }
+
+    /// skips all updates that arrived before, so only messages sent from now on
+    /// are received
+    pub fn skip_pending(&mut self) -> Result<()> {
+        // an offset of -1 returns only the last update and confirms all before
+        for update in try!(self.api.get_updates(-1, 0)) {
+            self.advance(&update);
+        }
+        Ok(())
+    }
+
+    /// waits at most `timeout` seconds for new updates of any kind and returns them
+    pub fn poll_updates(&mut self, timeout: u32) -> Result<Vec<Value>> {
+        let updates = try!(self.api.get_updates(self.offset, timeout));
+        for update in updates.iter() {
+            self.advance(update);
+        }
+        Ok(updates)
+    }
+
+    /// waits at most `timeout` seconds for new messages and returns them;
+    /// posts in channels count as messages too
+    pub fn poll(&mut self, timeout: u32) -> Result<Vec<Value>> {
+        Ok(try!(self.poll_updates(timeout))
+            .iter()
+            .filter_map(|update| botapi::update_message(update).cloned())
+            .collect())
+    }
+
+    /// tells telegram that all received updates were handled,
+    /// so they are not received again by the next listener
+    pub fn acknowledge(&self) -> Result<()> {
+        try!(self.api.get_updates(self.offset, 0));
+        Ok(())
+    }
+
+    /// like `acknowledge`, but only up to and including the given update;
+    /// later updates that were already received are received again by the next listener
+    pub fn acknowledge_through(&mut self, update: &Value) -> Result<()> {
+        self.advance(update);
+        self.acknowledge()
+    }
+
+    fn advance(&mut self, update: &Value) {
+        if let Some(id) = update.find("update_id").and_then(|i| i.as_i64()) {
+            self.offset = id + 1;
+        }
+    }
+}
@@ -0,0 +1,213 @@
+extern crate log;
+
+use self::log::{Log, LogLevelFilter, LogMetadata, LogRecord, SetLoggerError};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use teleecho::teleecho::{DeliveryReport, SENDER_THREAD_NAME, TeleechoProcessor};
+
+/// by default records are dropped if this many messages wait to be sent
+pub const DEFAULT_QUEUE_CAPACITY: usize = 1000;
+
+/// turns a record into the text that is sent
+pub type Formatter = Box<Fn(&LogRecord) -> String + Send + Sync>;
+
+fn default_format(record: &LogRecord) -> String {
+    format!("{} {}: {}", record.level(), record.target(), record.args())
+}
+
+/// a backend for the `log` crate that sends records with a `TeleechoProcessor`,
+/// e.g. to get warnings and errors of a daemon on the phone:
+///
+/// ```no_run
+/// extern crate log;
+/// extern crate teleecho;
+///
+/// # fn main() {
+/// let processor = teleecho::TeleechoProcessor::builder("123456:ABC-DEF1234ghIkl")
+///     .chat(12345678)
+///     .build()
+///     .unwrap();
+/// let handle = teleecho::TeleechoLogger::new(processor)
+///     .level(log::LogLevelFilter::Warn)
+///     .target("mydaemon::jobs", log::LogLevelFilter::Info)
+///     .init()
+///     .unwrap();
+///
+/// // before exiting, so queued records are not lost
+/// handle.flush();
+/// # }
+/// ```
+///
+/// records are only queued, so logging never waits for telegram; if too many
+/// messages wait to be sent, records are dropped and the number of dropped
+/// records is sent later. records logged while sending are ignored, as
+/// otherwise sending would log again
+pub struct TeleechoLogger {
+    queue: RecordQueue,
+    level: LogLevelFilter,
+
+    /// levels for targets starting with the given path; the longest match wins
+    targets: Vec<(String, LogLevelFilter)>,
+    format: Formatter,
+}
+
+/// the processor of a logger or tracing layer. records are queued without
+/// waiting for telegram, or dropped if too many messages wait; the number of
+/// dropped records is sent with the next one
+pub struct RecordQueue {
+    processor: Arc<Mutex<TeleechoProcessor>>,
+    capacity: usize,
+    dropped: AtomicUsize,
+
+    /// what the records are called when reporting dropped ones, e.g. `log records`
+    what: &'static str,
+}
+
+impl RecordQueue {
+    pub fn new(processor: TeleechoProcessor, what: &'static str) -> RecordQueue {
+        RecordQueue {
+            processor: Arc::new(Mutex::new(processor)),
+            capacity: DEFAULT_QUEUE_CAPACITY,
+            dropped: AtomicUsize::new(0),
+            what: what,
+        }
+    }
+
+    /// records are dropped while this many messages wait to be sent
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+    }
+
+    /// a handle to flush the processor
+    pub fn handle(&self) -> TeleechoLoggerHandle {
+        TeleechoLoggerHandle { processor: self.processor.clone() }
+    }
+
+    /// queues the text `record` returns as line, unless it returns None.
+    /// returns the queue mark after the record, or None if nothing was queued
+    pub fn push<F>(&self, record: F) -> Option<usize>
+        where F: FnOnce(&TeleechoProcessor) -> Option<String>
+    {
+        // the lock is only held while queueing, never while sending
+        let mut processor = self.processor.lock().unwrap();
+
+        let record = match record(&*processor) {
+            Some(record) => record,
+            None => return None,
+        };
+        if processor.queued_messages() >= self.capacity {
+            self.dropped.fetch_add(1, Ordering::SeqCst);
+            return None;
+        }
+
+        let mut text = String::new();
+        let dropped = self.dropped.swap(0, Ordering::SeqCst);
+        if dropped > 0 {
+            text.push_str(&format!("({} {} dropped)\n", dropped, self.what));
+        }
+        // a carriage return would replace the last line
+        text.push_str(&record.replace('\r', ""));
+        text.push('\n');
+
+        for c in text.chars() {
+            processor.append_to_input_buffer(c);
+        }
+        Some(processor.queue_mark())
+    }
+}
+
+/// gives access to the processor after the logger or layer was installed
+pub struct TeleechoLoggerHandle {
+    processor: Arc<Mutex<TeleechoProcessor>>,
+}
+
+impl TeleechoLoggerHandle {
+    /// waits until all queued records are sent;
+    /// returns whether every record so far was delivered.
+    /// the processor is not locked while waiting, so logging goes on meanwhile
+    pub fn flush(&self) -> bool {
+        let receiver = self.processor.lock().unwrap().request_report();
+        let report = receiver.recv().unwrap_or(DeliveryReport::default());
+        self.processor.lock().unwrap().add_report(report).is_ok()
+    }
+}
+
+impl TeleechoLogger {
+    /// a logger sending warnings and errors with the given processor
+    pub fn new(processor: TeleechoProcessor) -> TeleechoLogger {
+        TeleechoLogger {
+            queue: RecordQueue::new(processor, "log records"),
+            level: LogLevelFilter::Warn,
+            targets: vec![],
+            format: Box::new(default_format),
+        }
+    }
+
+    /// the minimum level of records that are sent
+    pub fn level(mut self, level: LogLevelFilter) -> TeleechoLogger {
+        self.level = level;
+        self
+    }
+
+    /// the minimum level for records of the given target and the modules below it,
+    /// e.g. `mydaemon::jobs`
+    pub fn target(mut self, target: &str, level: LogLevelFilter) -> TeleechoLogger {
+        self.targets.push((String::from(target), level));
+        self
+    }
+
+    /// replaces the default format `LEVEL target: message`
+    pub fn format<F>(mut self, format: F) -> TeleechoLogger
+        where F: Fn(&LogRecord) -> String + Send + Sync + 'static
+    {
+        self.format = Box::new(format);
+        self
+    }
+
+    /// records are dropped while this many messages wait to be sent
+    pub fn queue_capacity(mut self, capacity: usize) -> TeleechoLogger {
+        self.queue.set_capacity(capacity);
+        self
+    }
+
+    /// installs this as the logger of the `log` crate
+    pub fn init(self) -> Result<TeleechoLoggerHandle, SetLoggerError> {
+        let handle = self.queue.handle();
+        try!(log::set_logger(|max_level| {
+            max_level.set(self.max_level());
+            Box::new(self)
+        }));
+        Ok(handle)
+    }
+
+    fn max_level(&self) -> LogLevelFilter {
+        self.targets.iter().map(|&(_, level)| level).fold(self.level, ::std::cmp::max)
+    }
+
+    fn level_for(&self, target: &str) -> LogLevelFilter {
+        self.targets
+            .iter()
+            .filter(|&&(ref prefix, _)| {
+                target == prefix || target.starts_with(&format!("{}::", prefix))
+            })
+            .max_by_key(|&&(ref prefix, _)| prefix.len())
+            .map(|&(_, level)| level)
+            .unwrap_or(self.level)
+    }
+}
+
+impl Log for TeleechoLogger {
+    fn enabled(&self, metadata: &LogMetadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target()) &&
+        thread::current().name() != Some(SENDER_THREAD_NAME)
+    }
+
+    fn log(&self, record: &LogRecord) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        self.queue.push(|_| Some((self.format)(record)));
+    }
+}
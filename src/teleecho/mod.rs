@@ -1,3 +1,22 @@
 pub mod teleecho;
 pub mod error;
 pub mod config;
+pub mod envelope;
+pub mod botapi;
+pub mod listener;
+pub mod logger;
+#[cfg(feature = "tracing-layer")]
+pub mod tracing_layer;
+
+// the subcommands of the teleecho command; they are only public so the command
+// can use them, and are not part of the library
+#[doc(hidden)]
+pub mod doctor;
+#[doc(hidden)]
+pub mod pairing;
+#[doc(hidden)]
+pub mod run;
+#[doc(hidden)]
+pub mod ask;
+#[doc(hidden)]
+pub mod read;
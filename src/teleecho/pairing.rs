@@ -0,0 +1,409 @@
+extern crate rand;
+extern crate serde_json;
+extern crate qrcode;
+
+use self::qrcode::{Color, QrCode};
+use self::rand::{OsRng, Rng};
+use self::serde_json::Value;
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::{Duration, Instant};
+use teleecho::botapi::{unexpected_answer, BotApi};
+use teleecho::error::*;
+use teleecho::listener::{Listener, POLL_TIMEOUT};
+use teleecho::teleecho::{Recipient, SendOptions};
+
+/// after this many wrong pairing attempts of a sender, its messages are ignored
+const MAX_WRONG_ATTEMPTS: u32 = 5;
+
+/// the default time in seconds a pairing nonce stays valid
+pub const DEFAULT_PAIRING_TIMEOUT: u64 = 600;
+
+/// the chat a new connection was paired with
+#[derive(Debug, Clone)]
+pub struct Pairing {
+    pub chat_id: i64,
+
+    /// `private`, `group`, `supergroup` or `channel`
+    pub chat_type: String,
+
+    /// the title of a group or channel, or the name of the user
+    pub chat_title: String,
+
+    /// name and id of the user who sent the nonce, if known
+    pub user: Option<(String, i64)>,
+
+    /// the forum topic the nonce was sent in, with its name if known
+    pub thread_id: Option<i64>,
+    pub topic_name: Option<String>,
+}
+
+impl Pairing {
+    fn from_chat(chat: &Value) -> Option<Pairing> {
+        let id = match chat.find("id").and_then(|i| i.as_i64()) {
+            Some(id) => id,
+            None => return None,
+        };
+        let chat_type = chat.find("type").and_then(|t| t.as_str()).unwrap_or("private");
+        let title = chat.find("title")
+            .or(chat.find("first_name"))
+            .and_then(|t| t.as_str())
+            .unwrap_or("");
+
+        Some(Pairing {
+            chat_id: id,
+            chat_type: String::from(chat_type),
+            chat_title: String::from(title),
+            user: None,
+            thread_id: None,
+            topic_name: None,
+        })
+    }
+}
+
+/// creates a new random 128 bit nonce, hex encoded;
+/// this only uses characters that are allowed in a deep link start parameter
+fn generate_nonce() -> Result<String> {
+    let mut rng = try!(OsRng::new());
+    let mut bytes = [0u8; 16];
+    rng.fill_bytes(&mut bytes);
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<String>>().join(""))
+}
+
+/// renders the given text as qr code with unicode half blocks,
+/// so two rows of modules fit into one line of the terminal.
+/// light modules are drawn as blocks, as most terminals are dark
+fn render_qr(text: &str) -> Result<String> {
+    let code = try!(QrCode::new(text.as_bytes())
+                        .map_err(|e| Error::from(ErrorKind::QrCode(format!("{:?}", e)))));
+    let width = code.width();
+    let colors = code.to_colors();
+
+    // the quiet zone around the code, which scanners need to find it
+    let border = 2;
+    let size = width + 2 * border;
+    let is_light = |x: usize, y: usize| {
+        if x < border || y < border || x >= width + border || y >= width + border {
+            true
+        } else {
+            colors[(y - border) * width + (x - border)] == Color::Light
+        }
+    };
+
+    let mut out = String::new();
+    for y in (0..size).filter(|y| y % 2 == 0) {
+        for x in 0..size {
+            let top = is_light(x, y);
+            let bottom = y + 1 < size && is_light(x, y + 1);
+            out.push(match (top, bottom) {
+                (true, true) => '█',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (false, false) => ' ',
+            });
+        }
+        out.push('\n');
+    }
+
+    Ok(out)
+}
+
+/// returns full name and id of the sender of the given message, if known
+fn sender(message: &Value) -> Option<(String, i64)> {
+    let id = match message.lookup("from.id").and_then(|i| i.as_i64()) {
+        Some(id) => id,
+        None => return None,
+    };
+
+    let mut name = message.lookup("from.first_name")
+        .and_then(|n| n.as_str())
+        .unwrap_or("")
+        .to_string();
+    if let Some(last) = message.lookup("from.last_name").and_then(|n| n.as_str()) {
+        name.push(' ');
+        name.push_str(last);
+    }
+    if let Some(username) = message.lookup("from.username").and_then(|n| n.as_str()) {
+        name.push_str(&format!(" (@{})", username));
+    }
+
+    Some((name, id))
+}
+
+/// given the text of a message this returns the pairing code it contains.
+/// this is the text itself, or the argument of `/start` or `/pair`.
+/// `/start <nonce>` is what telegram sends when the deep link is opened;
+/// the command may be addressed to this bot like `/pair@somebot <nonce>`,
+/// which is needed in groups where the bot runs in privacy mode and only sees commands
+fn pairing_code<'a>(text: &'a str, bot_username: &str) -> Option<&'a str> {
+    let text = text.trim();
+    if !text.starts_with('/') {
+        return Some(text);
+    }
+
+    let mut parts = text.splitn(2, char::is_whitespace);
+    let mut command = parts.next().unwrap().splitn(2, '@');
+    let name = command.next().unwrap();
+
+    // commands addressed to other bots are ignored
+    if let Some(addressed) = command.next() {
+        if !addressed.eq_ignore_ascii_case(bot_username) {
+            return None;
+        }
+    }
+
+    if name == "/pair" || name == "/start" {
+        parts.next().map(|code| code.trim())
+    } else {
+        None
+    }
+}
+
+/// the id of the user who sent the given message; posts in channels
+/// have no sender, so the channel counts as sender
+fn sender_id(message: &Value) -> Option<i64> {
+    message.lookup("from.id").or(message.lookup("chat.id")).and_then(|i| i.as_i64())
+}
+
+/// returns the name of the sender of the given message for printing
+fn sender_name(message: &Value) -> String {
+    message.lookup("from.first_name")
+        .or(message.lookup("chat.title"))
+        .and_then(|n| n.as_str())
+        .unwrap_or("unknown")
+        .to_string()
+}
+
+/// checks that the bot is an administrator of the given channel who may post
+fn check_channel_admin(api: &BotApi, channel_id: i64, bot_id: i64) -> Result<()> {
+    let member = try!(api.get_chat_member(channel_id, bot_id));
+    let status = member.find("status").and_then(|s| s.as_str()).unwrap_or("");
+    let can_post = member.find("can_post_messages").and_then(|c| c.as_bool()).unwrap_or(true);
+
+    if (status == "administrator" && can_post) || status == "creator" {
+        Ok(())
+    } else {
+        Err(ErrorKind::PairingNotChannelAdmin.into())
+    }
+}
+
+/// given a token this starts a listener for telegram messages.
+/// if the randomly generated nonce is sent via telegram to this bot,
+/// which happens when the printed deep link is opened, the chat it
+/// was sent in is returned.
+///
+/// this works in a private chat, in groups with `/pair <nonce>`,
+/// by posting the nonce in a channel the bot is administrator of, or
+/// by forwarding such a channel post to the bot.
+///
+/// the nonce is only valid for `timeout` seconds, and after a few wrong
+/// attempts the messages of a sender are ignored, so it can not be guessed.
+/// messages sent before pairing started are ignored too.
+/// if `show_qr` is set, the link is also printed as qr code to scan with the phone
+pub fn register_connection(token: &str, timeout: u64, show_qr: bool) -> Result<Pairing> {
+    register_connection_inner(token, timeout, show_qr).map_err(|e| redact(e, token))
+}
+
+fn register_connection_inner(token: &str, timeout: u64, show_qr: bool) -> Result<Pairing> {
+
+    let api = BotApi::new(token);
+    let mut listener = Listener::new(BotApi::new(token));
+    let me = try!(api.get_me());
+    let bot_id = try!(me.find("id")
+        .and_then(|i| i.as_i64())
+        .ok_or(unexpected_answer("getMe returned no id")));
+    let bot_username = me.find("username").and_then(|u| u.as_str()).unwrap_or("").to_string();
+
+    let nonce = try!(generate_nonce());
+
+    // old messages to the bot are no pairing attempts
+    try!(listener.skip_pending());
+
+    let link = format!("https://t.me/{}?start={}", bot_username, nonce);
+
+    // the instructions go to stderr, so stdout only holds the result
+    let mut out = ::std::io::stderr();
+    try!(writeln!(out,
+                  "open the following link and press start to pair with the @{} bot:",
+                  bot_username));
+    if show_qr {
+        try!(write!(out, "{}", try!(render_qr(&link))));
+    }
+    try!(writeln!(out, "\t{}", link));
+    try!(writeln!(out,
+                  "to pair a group open https://t.me/{}?startgroup={} or send `/pair {}` there,",
+                  bot_username,
+                  nonce,
+                  nonce));
+    try!(writeln!(out,
+                  "for a channel post the nonce in the channel or forward such a post to the \
+                   bot."));
+    try!(writeln!(out,
+                  "the link is valid for {} seconds; press Ctrl-C to cancel",
+                  timeout));
+
+    let expires = Instant::now() + Duration::from_secs(timeout);
+    let mut wrong_attempts: HashMap<i64, u32> = HashMap::new();
+
+    loop {
+        let now = Instant::now();
+        if now >= expires {
+            return Err(ErrorKind::PairingExpired.into());
+        }
+
+        // do not poll longer than the nonce is valid
+        let remaining = (expires - now).as_secs() as u32;
+        let poll_timeout = ::std::cmp::min(remaining, POLL_TIMEOUT);
+
+        for message in try!(listener.poll(poll_timeout)) {
+            let message = &message;
+
+            let text = match message.find("text").and_then(|t| t.as_str()) {
+                Some(t) => t,
+                None => continue,
+            };
+
+            // in groups only commands count as pairing attempt, as the
+            // bot may see all the chatter there
+            let chat_type = message.lookup("chat.type").and_then(|t| t.as_str()).unwrap_or("");
+            if (chat_type == "group" || chat_type == "supergroup") && !text.starts_with('/') {
+                continue;
+            }
+
+            // messages that are no pairing attempt at all are ignored
+            let code = match pairing_code(text, &bot_username) {
+                Some(code) => code,
+                None => continue,
+            };
+
+            // a sender that guessed too often is locked out, even with the right nonce;
+            // others can still pair
+            let attempts = wrong_attempts.entry(sender_id(message).unwrap_or(0)).or_insert(0);
+            if *attempts >= MAX_WRONG_ATTEMPTS {
+                continue;
+            }
+
+            if code != nonce {
+                *attempts += 1;
+                try!(writeln!(out, "received wrong nonce from {}", sender_name(message)));
+
+                if *attempts >= MAX_WRONG_ATTEMPTS {
+                    try!(writeln!(out,
+                                  "ignoring {} after {} wrong attempts",
+                                  sender_name(message),
+                                  attempts));
+                }
+                continue;
+            }
+
+            // a post forwarded from a channel pairs the channel, not the private chat
+            let chat = message.find("forward_from_chat")
+                .and_then(|c| if c.find("type").and_then(|t| t.as_str()) == Some("channel") {
+                    Some(c)
+                } else {
+                    None
+                })
+                .or(message.find("chat"));
+
+            let mut pairing = match chat.and_then(Pairing::from_chat) {
+                Some(p) => p,
+                None => continue,
+            };
+            pairing.user = sender(message);
+
+            // a nonce sent inside a forum topic pairs with this topic
+            if message.find("is_topic_message").and_then(|t| t.as_bool()) == Some(true) {
+                pairing.thread_id = message.find("message_thread_id").and_then(|t| t.as_i64());
+                pairing.topic_name = message.lookup("reply_to_message.forum_topic_created.name")
+                    .and_then(|n| n.as_str())
+                    .map(String::from);
+            }
+
+            if pairing.chat_type == "channel" {
+                try!(check_channel_admin(&api, pairing.chat_id, bot_id));
+            }
+
+            // notify the user in the chat the number was sent in
+            // but dont fail if this did not work
+            if let Some(reply_to) = message.lookup("chat.id").and_then(|i| i.as_i64()) {
+                let recipient = Recipient {
+                    chat_id: reply_to,
+                    thread_id: pairing.thread_id,
+                };
+                if let Err(err) = api.send_text(recipient,
+                                                "paired with teleecho!",
+                                                &SendOptions::default()) {
+                    try!(writeln!(out, "Error while register {}", err));
+                }
+            }
+
+            try!(listener.acknowledge());
+
+            return Ok(pairing);
+        }
+    }
+}
+
+/// verifies a chat id that is already known without listening:
+/// the bot must be valid and be able to send a test message to the chat
+pub fn verify_chat(token: &str, chat_id: i64) -> Result<Pairing> {
+    verify_chat_inner(token, chat_id).map_err(|e| redact(e, token))
+}
+
+fn verify_chat_inner(token: &str, chat_id: i64) -> Result<Pairing> {
+    let api = BotApi::new(token);
+    try!(api.get_me());
+
+    // bots may not be able to look up private chats, so this is only best effort
+    let mut pairing = api.get_chat(chat_id)
+        .ok()
+        .and_then(|chat| Pairing::from_chat(&chat))
+        .unwrap_or(Pairing {
+            chat_id: chat_id,
+            chat_type: String::from(if chat_id > 0 { "private" } else { "group" }),
+            chat_title: String::new(),
+            user: None,
+            thread_id: None,
+            topic_name: None,
+        });
+    pairing.chat_id = chat_id;
+
+    let recipient = Recipient {
+        chat_id: chat_id,
+        thread_id: pairing.thread_id,
+    };
+    let options = SendOptions { silent: true, ..SendOptions::default() };
+    try!(api.send_text(recipient, "paired with teleecho!", &options));
+    Ok(pairing)
+}
+
+/// pairs with the channel of the given @username without listening;
+/// the bot must be an administrator of the channel who may post
+pub fn register_channel(token: &str, channel: &str) -> Result<Pairing> {
+    register_channel_inner(token, channel).map_err(|e| redact(e, token))
+}
+
+fn register_channel_inner(token: &str, channel: &str) -> Result<Pairing> {
+    let api = BotApi::new(token);
+    let me = try!(api.get_me());
+    let bot_id = try!(me.find("id")
+        .and_then(|i| i.as_i64())
+        .ok_or(unexpected_answer("getMe returned no id")));
+
+    let username = if channel.starts_with('@') {
+        String::from(channel)
+    } else {
+        format!("@{}", channel)
+    };
+
+    let chat = try!(api.get_chat_by_username(&username));
+    let pairing = try!(Pairing::from_chat(&chat)
+        .ok_or(unexpected_answer("getChat returned no chat id")));
+
+    if pairing.chat_type != "channel" {
+        return Err(ErrorKind::PairingNotAChannel(username).into());
+    }
+
+    try!(check_channel_admin(&api, pairing.chat_id, bot_id));
+    Ok(pairing)
+}
@@ -0,0 +1,130 @@
+extern crate serde_json;
+
+use self::serde_json::Value;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind as IoErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use teleecho::botapi::{self, BotApi};
+use teleecho::error::*;
+use teleecho::listener::{Listener, POLL_TIMEOUT};
+
+/// returns the id of the file attached to the given message and a name to store it as.
+/// photos come in several sizes, the largest one is taken
+fn attachment(message: &Value) -> Option<(String, String)> {
+    if let Some(document) = message.find("document") {
+        let id = match document.find("file_id").and_then(|i| i.as_str()) {
+            Some(id) => id,
+            None => return None,
+        };
+        let name = document.find("file_name")
+            .and_then(|n| n.as_str())
+            .map(String::from)
+            .unwrap_or(format!("document_{}", id));
+        return Some((String::from(id), name));
+    }
+
+    message.find("photo")
+        .and_then(|p| p.as_array())
+        .and_then(|sizes| sizes.last())
+        .and_then(|photo| photo.find("file_id").and_then(|i| i.as_str()))
+        .map(|id| (String::from(id), format!("photo_{}.jpg", id)))
+}
+
+/// creates a new file for the given name in the given directory; only the last
+/// component of the name is used, and a number is added if the file exists
+fn create_file(dir: &Path, name: &str) -> Result<(PathBuf, ::std::fs::File)> {
+    let name = Path::new(name)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("file")
+        .to_string();
+
+    let mut path = dir.join(&name);
+    let mut number = 1;
+    loop {
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(ref e) if e.kind() == IoErrorKind::AlreadyExists => {
+                path = dir.join(format!("{}.{}", name, number));
+                number += 1;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// downloads the given file into the given directory and returns its path
+fn download(api: &BotApi, file_id: &str, name: &str, dir: &Path) -> Result<PathBuf> {
+    let file = try!(api.get_file(file_id));
+    let file_path = try!(file.find("file_path")
+        .and_then(|p| p.as_str())
+        .ok_or(Error::from(ErrorKind::NoFilePath)));
+    let content = try!(api.download(file_path));
+
+    let (path, mut out) = try!(create_file(dir, name));
+    try!(out.write_all(&content));
+    Ok(path)
+}
+
+/// waits for the next text message, document or photo sent in one of the given chats.
+/// returns the text, or the path the file was downloaded to inside `dir`,
+/// or None if nothing was sent within `timeout` seconds.
+/// without a timeout this waits until something is sent.
+/// the message is acknowledged, so it is not read twice
+pub fn read(token: &str,
+            chats: &[i64],
+            timeout: Option<u64>,
+            dir: &Path)
+            -> Result<Option<String>> {
+    read_inner(token, chats, timeout, dir).map_err(|e| redact(e, token))
+}
+
+fn read_inner(token: &str,
+              chats: &[i64],
+              timeout: Option<u64>,
+              dir: &Path)
+              -> Result<Option<String>> {
+    let api = try!(BotApi::from_token(token));
+    let mut listener = Listener::new(BotApi::new(token));
+
+    let expires = timeout.map(|t| Instant::now() + Duration::from_secs(t));
+    loop {
+        let poll_timeout = match expires {
+            Some(expires) => {
+                let now = Instant::now();
+                if now >= expires {
+                    return Ok(None);
+                }
+                ::std::cmp::min((expires - now).as_secs() as u32 + 1, POLL_TIMEOUT)
+            }
+            None => POLL_TIMEOUT,
+        };
+
+        for update in try!(listener.poll_updates(poll_timeout)) {
+            let message = match botapi::update_message(&update) {
+                Some(m) => m,
+                None => continue,
+            };
+
+            // messages from other chats are not meant for this connection
+            match message.lookup("chat.id").and_then(|i| i.as_i64()) {
+                Some(id) if chats.contains(&id) => {}
+                _ => continue,
+            }
+
+            let result = if let Some(text) = message.find("text").and_then(|t| t.as_str()) {
+                String::from(text)
+            } else if let Some((file_id, name)) = attachment(message) {
+                let path = try!(download(&api, &file_id, &name, dir));
+                path.to_string_lossy().into_owned()
+            } else {
+                continue;
+            };
+
+            // only up to this message, so anything sent after it is read next time
+            try!(listener.acknowledge_through(&update));
+            return Ok(Some(result));
+        }
+    }
+}
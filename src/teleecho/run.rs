@@ -0,0 +1,452 @@
+extern crate libc;
+extern crate serde_json;
+
+use self::serde_json::Value;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::process::{ChildStdin, Command, Stdio};
+use std::str;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use teleecho::ask::may_answer;
+use teleecho::botapi::{self, BotApi};
+use teleecho::config::ChatTarget;
+use teleecho::error::*;
+use teleecho::listener::{Listener, POLL_TIMEOUT};
+use teleecho::teleecho::{Recipient, SendOptions, TeleechoProcessor};
+
+/// if the command writes nothing for this long, an incomplete line is sent anyway,
+/// as it is probably a prompt waiting for an answer
+const PROMPT_DELAY_MS: u64 = 500;
+
+/// the seconds to wait before polling again after an error
+const RETRY_DELAY: u64 = 5;
+
+/// the number of lines kept for `/tail`
+const MAX_TAIL_LINES: usize = 500;
+
+/// the number of lines `/tail` sends if no number is given
+const DEFAULT_TAIL_LINES: usize = 10;
+
+/// the maximum length of a telegram message in chars
+const MAX_MESSAGE_LENGTH: usize = 4096;
+
+/// the commands that can be sent from the chat while the command runs
+const BOT_COMMANDS: &'static [(&'static str, &'static str)] =
+    &[("status", "elapsed time, lines seen and messages queued"),
+      ("tail", "the last lines of output, e.g. /tail 50"),
+      ("mute", "stop sending the output"),
+      ("unmute", "send the output again"),
+      ("kill", "terminate the running command")];
+
+/// what the threads reading the output of the command send,
+/// with the index of the stream it was read from
+enum Output {
+    Bytes(usize, Vec<u8>),
+    Closed(usize),
+}
+
+/// what is known about the running command;
+/// shared with the thread answering the bot commands
+struct JobState {
+    started: Instant,
+
+    /// the process id of the command, until it has exited
+    pid: Option<u32>,
+
+    /// the number of complete lines written by the command
+    lines: u64,
+
+    /// the line currently written and the last complete lines
+    line: String,
+    tail: VecDeque<String>,
+
+    /// the number of messages waiting to be sent
+    queued: usize,
+
+    /// if set, the output is not sent, but still counted and kept for `/tail`
+    muted: bool,
+}
+
+impl JobState {
+    fn new(pid: u32) -> JobState {
+        JobState {
+            started: Instant::now(),
+            pid: Some(pid),
+            lines: 0,
+            line: String::new(),
+            tail: VecDeque::with_capacity(MAX_TAIL_LINES),
+            queued: 0,
+            muted: false,
+        }
+    }
+
+    fn record(&mut self, c: char) {
+        match c {
+            '\n' => {
+                if self.tail.len() >= MAX_TAIL_LINES {
+                    self.tail.pop_front();
+                }
+                self.tail.push_back(self.line.clone());
+                self.line.clear();
+                self.lines += 1;
+            }
+            // like the sender, a carriage return replaces the current line
+            '\r' => self.line.clear(),
+            c => self.line.push(c),
+        }
+    }
+
+    fn status(&self) -> String {
+        let elapsed = self.started.elapsed().as_secs();
+        format!("running for {}h {}m {}s, {} lines seen, {} messages queued{}",
+                elapsed / 3600,
+                elapsed / 60 % 60,
+                elapsed % 60,
+                self.lines,
+                self.queued,
+                if self.muted { ", muted" } else { "" })
+    }
+
+    /// the last `count` lines, but only as many as fit into one message
+    fn tail(&self, count: usize) -> String {
+        let mut lines = VecDeque::new();
+        let mut length = 0;
+        for line in self.tail.iter().rev().take(count) {
+            let line_length = line.chars().count() + 1;
+            if length + line_length > MAX_MESSAGE_LENGTH {
+                break;
+            }
+            length += line_length;
+            lines.push_front(line.as_str());
+        }
+
+        if lines.is_empty() {
+            String::from("no output yet")
+        } else {
+            lines.into_iter().collect::<Vec<&str>>().join("\n")
+        }
+    }
+}
+
+// reads the given output of the command and sends the bytes as they come
+fn forward_output<R: Read + Send + 'static>(stream: usize, output: R, sender: Sender<Output>) {
+    thread::spawn(move || {
+        let mut output = BufReader::new(output);
+        loop {
+            let length = match output.fill_buf() {
+                Ok(bytes) if bytes.is_empty() => break,
+                Ok(bytes) => {
+                    if sender.send(Output::Bytes(stream, bytes.to_vec())).is_err() {
+                        return;
+                    }
+                    bytes.len()
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            output.consume(length);
+        }
+        let _ = sender.send(Output::Closed(stream));
+    });
+}
+
+/// takes the complete lines from the given bytes read from one stream,
+/// so lines of stdout and stderr are never mixed
+fn take_lines(pending: &mut Vec<u8>) -> Vec<u8> {
+    match pending.iter().rposition(|&b| b == b'\n') {
+        Some(end) => {
+            let rest = pending.split_off(end + 1);
+            ::std::mem::replace(pending, rest)
+        }
+        None => vec![],
+    }
+}
+
+/// takes all of the given bytes but an incomplete char at their end
+fn take_chars(pending: &mut Vec<u8>) -> Vec<u8> {
+    let start = pending.len().saturating_sub(3);
+    let end = (start..pending.len())
+        .rev()
+        .find(|&i| pending[i] & 0xc0 != 0x80)
+        .and_then(|i| match str::from_utf8(&pending[i..]) {
+            Err(ref e) if e.error_len().is_none() => Some(i),
+            _ => None,
+        })
+        .unwrap_or(pending.len());
+    let rest = pending.split_off(end);
+    ::std::mem::replace(pending, rest)
+}
+
+/// returns the text of the given message if it was sent in one of the given chats
+/// by someone who may answer there
+fn reply_text<'a>(message: &'a Value, chats: &[ChatTarget]) -> Option<&'a str> {
+    let id = message.lookup("chat.id").and_then(|i| i.as_i64());
+    if chats.iter().any(|c| Some(c.id) == id && may_answer(message, c)) {
+        message.find("text").and_then(|t| t.as_str())
+    } else {
+        None
+    }
+}
+
+/// splits a bot command like `/tail@somebot 50` into name and argument;
+/// returns None for anything that is not one of `BOT_COMMANDS` addressed to
+/// this bot, like a path or a command for another bot, which is meant for the command
+fn parse_command<'a>(text: &'a str, bot_username: &str) -> Option<(&'a str, Option<&'a str>)> {
+    if !text.starts_with('/') {
+        return None;
+    }
+
+    let mut parts = text.trim().splitn(2, char::is_whitespace);
+    let mut command = parts.next().unwrap().splitn(2, '@');
+    let name = command.next().unwrap().trim_left_matches('/');
+
+    if !BOT_COMMANDS.iter().any(|&(c, _)| c == name) {
+        return None;
+    }
+    if let Some(addressed) = command.next() {
+        if !addressed.eq_ignore_ascii_case(bot_username) {
+            return None;
+        }
+    }
+
+    Some((name, parts.next().map(|a| a.trim())))
+}
+
+/// sends the given signal to the process with the given id
+#[cfg(unix)]
+fn terminate(pid: u32) -> Result<()> {
+    if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error().into())
+    }
+}
+
+#[cfg(not(unix))]
+fn terminate(_: u32) -> Result<()> {
+    Err(ErrorKind::UnsupportedPlatform(String::from("terminating the command")).into())
+}
+
+/// runs the given bot command and returns the answer
+fn handle_command(name: &str, argument: Option<&str>, state: &Mutex<JobState>) -> String {
+    let mut state = state.lock().unwrap();
+    match name {
+        "status" => state.status(),
+        "tail" => {
+            match argument.map(|a| a.parse::<usize>()) {
+                None => state.tail(DEFAULT_TAIL_LINES),
+                Some(Ok(count)) => state.tail(count),
+                Some(Err(_)) => String::from("usage: /tail [LINES]"),
+            }
+        }
+        "mute" => {
+            state.muted = true;
+            String::from("muted; the output is not sent until /unmute")
+        }
+        "unmute" => {
+            state.muted = false;
+            String::from("unmuted")
+        }
+        "kill" => {
+            match state.pid {
+                Some(pid) => {
+                    match terminate(pid) {
+                        Ok(_) => format!("sent SIGTERM to process {}", pid),
+                        Err(err) => format!("could not terminate process {}: {}", pid, err),
+                    }
+                }
+                None => String::from("command has exited"),
+            }
+        }
+        // parse_command only returns the names of BOT_COMMANDS
+        _ => unreachable!(),
+    }
+}
+
+// long polls for messages from the given chats. bot commands for this bot are answered,
+// the text of every other message, including other commands, is written as a line
+// into the stdin of the command.
+// the thread ends when the command closes its stdin or the bot can not be used anymore;
+// otherwise it is left running when the command exits
+fn listen(api: BotApi,
+          chats: Vec<ChatTarget>,
+          mut stdin: ChildStdin,
+          state: Arc<Mutex<JobState>>) {
+    thread::spawn(move || {
+        let mut listener = Listener::new(api);
+
+        // answers sent before the command was started are not meant for it
+        if let Err(err) = listener.skip_pending() {
+            warn(&format!("error while listening for replies: {}", err));
+        }
+
+        // the commands are only suggested, so this may fail
+        if let Err(err) = listener.api().set_my_commands(BOT_COMMANDS) {
+            warn(&format!("error while registering bot commands: {}", err));
+        }
+        let bot_username = listener.api()
+            .get_me()
+            .ok()
+            .and_then(|me| me.find("username").and_then(|u| u.as_str()).map(String::from))
+            .unwrap_or(String::new());
+
+        loop {
+            let messages = match listener.poll(POLL_TIMEOUT) {
+                Ok(messages) => messages,
+                Err(err) => {
+                    // replies can not be received anymore, which is no mere warning
+                    if botapi::is_fatal(&err) {
+                        let _ = writeln!(io::stderr(),
+                                         "error: stopped listening for replies: {}",
+                                         err);
+                        return;
+                    }
+                    warn(&format!("error while listening for replies: {}", err));
+                    thread::sleep(Duration::from_secs(RETRY_DELAY));
+                    continue;
+                }
+            };
+
+            for message in messages.iter() {
+                let text = match reply_text(message, &chats) {
+                    Some(text) => text,
+                    None => continue,
+                };
+
+                let answer = match parse_command(text, &bot_username) {
+                    Some((name, argument)) => handle_command(name, argument, &state),
+                    None => {
+                        if writeln!(stdin, "{}", text).and_then(|_| stdin.flush()).is_err() {
+                            return;
+                        }
+                        continue;
+                    }
+                };
+
+                // answer in the chat and topic the command was sent in
+                let recipient = Recipient {
+                    chat_id: message.lookup("chat.id").and_then(|i| i.as_i64()).unwrap(),
+                    thread_id: message.find("message_thread_id").and_then(|t| t.as_i64()),
+                };
+                if let Err(err) = listener.api()
+                    .send_text(recipient, &answer, &SendOptions::default()) {
+                    warn(&format!("error while answering command: {}", err));
+                }
+            }
+        }
+    });
+}
+
+// keeps the given output for `/status` and `/tail` and sends it, unless muted
+fn record_output(tp: &mut TeleechoProcessor, state: &mut JobState, output: &[u8]) {
+    for c in String::from_utf8_lossy(output).chars() {
+        state.record(c);
+        if !state.muted {
+            tp.append_to_input_buffer(c);
+        }
+    }
+}
+
+/// runs the given command and sends everything it writes to stdout and stderr
+/// with the given processor. text replied in one of the given chats, by someone
+/// who may answer there, is written into the stdin of the command, so it can be
+/// answered from the phone;
+/// bot commands like `/status` or `/kill` control the running command.
+/// returns the exit code of the command
+pub fn run(tp: &mut TeleechoProcessor,
+           token: &str,
+           chats: Vec<ChatTarget>,
+           command: &[&str])
+           -> Result<i32> {
+    let mut child = try!(Command::new(command[0])
+                             .args(&command[1..])
+                             .stdin(Stdio::piped())
+                             .stdout(Stdio::piped())
+                             .stderr(Stdio::piped())
+                             .spawn()
+                             .map_err(|e| {
+                                 Error::from(ErrorKind::ChildFailed(command.join(" "),
+                                                                    format!("{}", e)))
+                             }));
+
+    let state = Arc::new(Mutex::new(JobState::new(child.id())));
+
+    let (sender, receiver) = mpsc::channel();
+    forward_output(0, child.stdout.take().unwrap(), sender.clone());
+    forward_output(1, child.stderr.take().unwrap(), sender);
+    listen(BotApi::new(token),
+           chats,
+           child.stdin.take().unwrap(),
+           state.clone());
+
+    // the bytes of stdout and stderr that are not part of a complete line yet
+    let mut pending = [vec![], vec![]];
+
+    // both stdout and stderr must be closed
+    let mut open = 2;
+    while open > 0 {
+        let event = receiver.recv_timeout(Duration::from_millis(PROMPT_DELAY_MS));
+
+        let mut state = state.lock().unwrap();
+        match event {
+            Ok(Output::Bytes(stream, bytes)) => {
+                pending[stream].extend_from_slice(&bytes);
+                let lines = take_lines(&mut pending[stream]);
+                record_output(tp, &mut state, &lines);
+            }
+            Ok(Output::Closed(stream)) => {
+                let rest = ::std::mem::replace(&mut pending[stream], vec![]);
+                record_output(tp, &mut state, &rest);
+                open -= 1;
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                for bytes in pending.iter_mut() {
+                    let chars = take_chars(bytes);
+                    record_output(tp, &mut state, &chars);
+                }
+                tp.flush_input_buffer();
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        state.queued = tp.queued_messages();
+    }
+    tp.flush_input_buffer();
+
+    let status = try!(child.wait());
+    state.lock().unwrap().pid = None;
+
+    // killed by a signal; report it like the shell does
+    Ok(status.code().unwrap_or(128))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn complete_lines_are_taken() {
+        let mut pending = b"one\ntwo\nthr".to_vec();
+        assert_eq!(take_lines(&mut pending), b"one\ntwo\n".to_vec());
+        assert_eq!(pending, b"thr".to_vec());
+        assert_eq!(take_lines(&mut pending), vec![]);
+        assert_eq!(pending, b"thr".to_vec());
+    }
+
+    #[test]
+    fn incomplete_chars_are_kept() {
+        let mut pending = vec![b'a', 0xe2, 0x82];
+        assert_eq!(take_chars(&mut pending), vec![b'a']);
+        assert_eq!(pending, vec![0xe2, 0x82]);
+
+        let mut pending = vec![b'a', 0xe2, 0x82, 0xac];
+        assert_eq!(take_chars(&mut pending), vec![b'a', 0xe2, 0x82, 0xac]);
+        assert!(pending.is_empty());
+
+        let mut pending = vec![b'a', 0xff];
+        assert_eq!(take_chars(&mut pending), vec![b'a', 0xff]);
+        assert!(pending.is_empty());
+    }
+}
@@ -1,18 +1,22 @@
-extern crate telegram_bot;
 extern crate time;
-extern crate rand;
 
-use rand::Rng;
+use teleecho::botapi::{self, BotApi, SentMessage};
+use teleecho::config::Connection;
 use teleecho::error::*;
+use std::io;
+use std::mem;
+use std::str;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::thread;
 use std::thread::JoinHandle;
 use std::sync::mpsc::{Sender, Receiver};
 use std::sync::mpsc;
 use std::collections::vec_deque::VecDeque;
 
-#[derive(Debug)]
-enum MessageBuffer {
+/// a line of input as it is queued for sending
+#[derive(Debug, Clone, PartialEq)]
+pub enum MessageBuffer {
     /// if the given text was preceded by a carriage return
     CarriageReturn(String),
 
@@ -20,63 +24,372 @@ enum MessageBuffer {
     Newline(String),
 }
 
+/// takes the next message to send from the given queue, which must not be empty.
+/// as many lines as fit into one telegram message are combined; a line
+/// replacing the last one is sent on its own
+pub fn combine_messages(message_buffer: &mut VecDeque<MessageBuffer>) -> MessageBuffer {
+    let to_send = message_buffer.pop_front().unwrap();
+
+    match to_send {
+        MessageBuffer::Newline(msg) => {
+            let mut message = msg;
+            let mut message_length = message.chars().count();
+            while message_buffer.len() > 0 {
+
+                let new_pop = {
+                    message_buffer.pop_front().unwrap()
+                };
+
+                if let MessageBuffer::Newline(msg) = new_pop {
+                    // count the chars and not just String.len()
+                    // as the limit is at 4096 utf8 chars defined by
+                    // the telegram api and not 4096 bytes which would be
+                    // String.len() >= 4096
+
+                    let this_message_length = msg.chars().count();
+
+                    if this_message_length + message_length + 1 >= 4096 {
+                        message_buffer.push_front(MessageBuffer::Newline(msg));
+                        break;
+                    } else {
+                        message.push('\n');
+                        message.push_str(&msg);
+                        message_length += this_message_length + 1;
+                    }
+                }
+            }
+
+            return MessageBuffer::Newline(message);
+        }
+        MessageBuffer::CarriageReturn(msg) => return MessageBuffer::CarriageReturn(msg),
+    }
+}
+
+/// appends the given line to the queue; a line replacing the last one
+/// replaces the last queued line instead, if any
+pub fn queue_message(message_buffer: &mut VecDeque<MessageBuffer>, msg: MessageBuffer) {
+    if message_buffer.len() == 0 {
+        message_buffer.push_back(msg);
+    } else if let &MessageBuffer::Newline(_) = &msg {
+        message_buffer.push_back(msg);
+    } else if let MessageBuffer::CarriageReturn(s) = msg {
+        // get last element; will exist, as len() > 0
+        let last_elem = message_buffer.pop_back().unwrap();
+
+        let new_elem = match last_elem {
+            MessageBuffer::CarriageReturn(_) => MessageBuffer::CarriageReturn(s),
+            MessageBuffer::Newline(_) => MessageBuffer::Newline(s),
+        };
+
+        message_buffer.push_back(new_elem);
+    }
+}
+
+/// given the text of a sent message this replaces its last line with the given one
+pub fn replace_last_line(text: &str, line: &str) -> String {
+    // split the old text that was sent by newlines
+    let mut parts = text.split("\n").collect::<Vec<&str>>();
+
+    // new when override last is called, the last \n part should be overriden
+    // so remove this
+    if parts.len() > 0 {
+        parts.pop();
+    }
+
+    // and push the new message there
+    parts.push(line);
+
+    // glue everything back together
+    parts.join("\n")
+}
+
 /// These are sent from the TeleechoProcessor to the sender to signal
 /// if a new element was added to the queue or the processor has ended
 #[derive(Debug)]
 enum BufferChangeEvent {
     NewElement,
+
+    /// asks for the report of everything sent since the last flush,
+    /// which is sent back once all earlier messages are handled
+    Flush(Sender<DeliveryReport>),
     Kill,
 }
 
-struct TeleechoSender {
-    /// the last sent message object,
+/// telegram allows about one message per second in the same chat
+pub const SEND_INTERVAL_NS: u64 = 1000000000;
+
+/// the name of the thread that sends the messages
+pub const SENDER_THREAD_NAME: &'static str = "teleecho sender";
+
+/// options that apply to every message sent
+#[derive(Debug, Clone, Default)]
+pub struct SendOptions {
+    /// how telegram should parse the text, `Markdown` or `HTML`;
+    /// None for plain text
+    pub parse_mode: Option<String>,
+
+    /// if set, no previews for links are shown
+    pub disable_web_page_preview: bool,
+
+    /// if set, messages arrive without a notification sound
+    pub silent: bool,
+
+    /// the id of a message in the chat to reply to; if it does not exist
+    /// the message is sent anyway
+    pub reply_to: Option<i64>,
+}
+
+/// a chat the messages are sent to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Recipient {
+    pub chat_id: i64,
+
+    /// the forum topic in a supergroup to send to; None for the general topic
+    pub thread_id: Option<i64>,
+}
+
+impl Recipient {
+    /// a recipient without a topic
+    pub fn chat(chat_id: i64) -> Recipient {
+        Recipient {
+            chat_id: chat_id,
+            thread_id: None,
+        }
+    }
+}
+
+/// a group that was upgraded to a supergroup, which has a new chat id
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChatMigration {
+    pub from: i64,
+    pub to: i64,
+}
+
+/// called by the send thread as soon as a chat was migrated, e.g. to store the new id
+pub type MigrationHook = Box<FnMut(ChatMigration) + Send>;
+
+/// a chat that can not be sent to anymore, e.g. because the bot was blocked
+#[derive(Debug)]
+pub struct DeliveryFailure {
+    pub chat_id: i64,
+    pub error: Error,
+}
+
+/// what happened to the messages given to a `TeleechoProcessor`;
+/// every chat counts separately
+#[derive(Debug, Default)]
+pub struct DeliveryReport {
+    /// the number of messages sent or edited
+    pub sent: usize,
+
+    /// the number of messages that could not be sent or edited
+    pub failed: usize,
+
+    /// the groups that were upgraded to supergroups; the messages were sent
+    /// to the new chat, whose id should be stored
+    pub migrations: Vec<ChatMigration>,
+
+    /// the chats that could not be sent to anymore, e.g. because the bot was
+    /// blocked or the token revoked
+    pub failures: Vec<DeliveryFailure>,
+}
+
+impl DeliveryReport {
+    /// whether every message was delivered
+    pub fn is_ok(&self) -> bool {
+        self.failed == 0 && self.failures.is_empty()
+    }
+
+    fn merge(&mut self, other: DeliveryReport) {
+        self.sent += other.sent;
+        self.failed += other.failed;
+        self.migrations.extend(other.migrations);
+        self.failures.extend(other.failures);
+    }
+}
+
+/// the state kept for every chat a sender sends to,
+/// so edits and failures are handled independently in each chat
+#[derive(Debug)]
+pub struct ChatState {
+    /// the chat and topic to send the messages to
+    pub recipient: Recipient,
+
+    /// the last sent message,
     /// this is needed to be able to edit the last message
-    last_sent_message: Option<telegram_bot::Message>,
+    pub last_sent_message: Option<SentMessage>,
+
+    /// set after a fatal error; nothing is sent to this chat anymore
+    pub failed: bool,
+}
+
+/// the chats a sender sends to and what happened so far.
+/// the blocking sender and the async one of teleecho-tokio both use this,
+/// so they treat errors and groups upgraded to supergroups alike
+#[derive(Debug)]
+pub struct SendState {
+    pub chats: Vec<ChatState>,
+
+    /// what happened since the last report was taken
+    pub report: DeliveryReport,
+
+    /// the token of the bot, only kept to keep it out of error messages
+    token: String,
+}
+
+impl SendState {
+    pub fn new(token: &str, recipients: &[Recipient]) -> SendState {
+        SendState {
+            chats: recipients.iter()
+                .map(|r| {
+                    ChatState {
+                        recipient: *r,
+                        last_sent_message: None,
+                        failed: false,
+                    }
+                })
+                .collect(),
+            report: DeliveryReport::default(),
+            token: String::from(token),
+        }
+    }
+
+    /// records a sent or edited message, which is the one to edit next
+    pub fn sent(&mut self, chat: usize, message: SentMessage) {
+        self.chats[chat].last_sent_message = Some(message);
+        self.report.sent += 1;
+    }
+
+    /// switches the given chat to the supergroup it was migrated to
+    /// and remembers this, so it can be stored in the config
+    pub fn migrate(&mut self, chat: usize, to: i64) -> ChatMigration {
+        let from = self.chats[chat].recipient.chat_id;
+        warn(&format!("chat {} was upgraded to supergroup {}, sending there from now on",
+                      from,
+                      to));
+
+        self.chats[chat].recipient.chat_id = to;
+        self.chats[chat].last_sent_message = None;
+
+        let migration = ChatMigration { from: from, to: to };
+        self.report.migrations.push(migration);
+        migration
+    }
+
+    /// fatal errors stop sending to the chat, or to all chats if the token
+    /// was rejected, and are reported once when closing;
+    /// other errors are printed and the next message is tried again
+    pub fn failed(&mut self, chat: usize, err: Error, action: &str) {
+        self.report.failed += 1;
+
+        let fatal = botapi::is_fatal(&err);
+        let all = botapi::is_token_error(&err);
+        let err = redact(err, &self.token);
+
+        if !fatal {
+            warn(&format!("error while {}: {}", action, err));
+            return;
+        }
+
+        for (i, c) in self.chats.iter_mut().enumerate() {
+            if all || i == chat {
+                c.failed = true;
+            }
+        }
+
+        self.report.failures.push(DeliveryFailure {
+            chat_id: self.chats[chat].recipient.chat_id,
+            error: err,
+        });
+    }
+
+    /// whether every chat failed, so nothing can be sent anymore
+    pub fn all_failed(&self) -> bool {
+        !self.chats.is_empty() && self.chats.iter().all(|c| c.failed)
+    }
+}
 
+/// the supergroup to send to instead, if the error says that the group
+/// was upgraded to one
+pub fn migrated_to<T>(result: &Result<T>) -> Option<i64> {
+    match *result {
+        Err(Error(ErrorKind::TelegramApi(_, _, Some(to)), _)) => Some(to),
+        _ => None,
+    }
+}
+
+struct TeleechoSender {
     /// reference to the api
-    api: telegram_bot::Api,
+    api: BotApi,
 
     /// a buffer that stores the messages to be sent
     message_buffer: Arc<Mutex<VecDeque<MessageBuffer>>>,
 
-    /// time in ns when the last message was sent
-    last_send_time: u64,
+    /// the chats to send the messages to and what happened since the last flush
+    state: SendState,
+
+    /// time in ns when the last message was sent to each chat
+    last_send_times: Vec<u64>,
+
+    /// the options for every sent or edited message
+    options: SendOptions,
+
+    /// set once every chat failed, so the processor can stop reading
+    all_failed: Arc<AtomicBool>,
 
-    /// the id to send the messages to
-    user_id: i64,
+    /// told about every migration right away, if set
+    migration_hook: Arc<Mutex<Option<MigrationHook>>>,
+
+    /// the number of messages taken from the buffer so far
+    taken: Arc<AtomicUsize>,
 }
 
 impl TeleechoSender {
-    fn create(api: telegram_bot::Api,
-              user_id: i64)
+    fn create(api: BotApi,
+              token: &str,
+              recipients: &[Recipient],
+              options: SendOptions,
+              migration_hook: Arc<Mutex<Option<MigrationHook>>>,
+              taken: Arc<AtomicUsize>)
               -> (Sender<BufferChangeEvent>,
-                  JoinHandle<()>,
-                  Arc<Mutex<VecDeque<MessageBuffer>>>) {
+                  JoinHandle<DeliveryReport>,
+                  Arc<Mutex<VecDeque<MessageBuffer>>>,
+                  Arc<AtomicBool>) {
 
         // create the sender object
         let ts = TeleechoSender {
-            last_sent_message: None,
             api: api,
             message_buffer: Arc::new(Mutex::new(VecDeque::with_capacity(4096))),
-            last_send_time: 0,
-            user_id: user_id,
+            state: SendState::new(token, recipients),
+            last_send_times: vec![0; recipients.len()],
+            options: options,
+            all_failed: Arc::new(AtomicBool::new(false)),
+            migration_hook: migration_hook,
+            taken: taken,
         };
 
         // create the copy of the buffer, where to processor writes to
         let buffer_copy = ts.message_buffer.clone();
+        let all_failed = ts.all_failed.clone();
 
         // and the sender/receiver object for communication
         let (sender, receiver) = mpsc::channel();
 
-        // now spawn the thread
-        let handle = thread::spawn(move || TeleechoSender::send_loop(ts, receiver));
+        // now spawn the thread; it is named, so log records of the http client
+        // sending the messages are not sent again by the logger
+        let handle = thread::Builder::new()
+            .name(String::from(SENDER_THREAD_NAME))
+            .spawn(move || TeleechoSender::send_loop(ts, receiver))
+            .unwrap();
 
         // return the necessary parts
-        (sender, handle, buffer_copy)
+        (sender, handle, buffer_copy, all_failed)
     }
 
 
-    fn send_loop(mut ts: TeleechoSender, receiver: Receiver<BufferChangeEvent>) {
+    /// sends until the kill event is received;
+    /// returns what happened since the last flush
+    fn send_loop(mut ts: TeleechoSender, receiver: Receiver<BufferChangeEvent>) -> DeliveryReport {
         loop {
             // the loop receives an event for every new message that is appended
             // or the kill request
@@ -84,155 +397,249 @@ impl TeleechoSender {
 
             // find out which was sent
             match event {
-                BufferChangeEvent::Kill => return,
+                BufferChangeEvent::Kill => return ts.state.report,
+                BufferChangeEvent::Flush(reply) => {
+                    let _ = reply.send(mem::replace(&mut ts.state.report,
+                                                    DeliveryReport::default()));
+                }
                 BufferChangeEvent::NewElement => {
 
-                    let time_diff = time::precise_time_ns() - ts.last_send_time;
-
-                    // send only every second
-                    if time_diff <= 1000000000u64 && ts.last_send_time != 0 {
-                        thread::sleep(::std::time::Duration::new(0,(1000000000u64 -
-                                                                          time_diff) as u32));
-                    }
+                    // wait for the first chat; meanwhile more messages
+                    // can arrive and be combined
+                    ts.wait_for_rate_limit(0);
 
                     // if a new message event is received this does not mean, that
                     // the buffer still has a message, as with the last message event this
                     // message could also have been sent already, as the messages get combined
                     if ts.message_buffer.lock().unwrap().len() > 0 {
 
-                        let to_send = TeleechoSender::combine_messages(&mut ts.message_buffer);
+                        let to_send = {
+                            let mut buffer = ts.message_buffer.lock().unwrap();
+                            let queued = buffer.len();
+                            let to_send = combine_messages(&mut buffer);
+                            ts.taken.fetch_add(queued - buffer.len(), Ordering::SeqCst);
+                            to_send
+                        };
 
-                        match to_send {
-                            MessageBuffer::Newline(msg) => ts.send(msg),
-                            MessageBuffer::CarriageReturn(msg) => ts.override_last(msg),
+                        for chat in 0..ts.state.chats.len() {
+                            if ts.state.chats[chat].failed {
+                                ts.state.report.failed += 1;
+                                continue;
+                            }
+                            ts.wait_for_rate_limit(chat);
+
+                            match to_send {
+                                MessageBuffer::Newline(ref msg) => ts.send(chat, msg.clone()),
+                                MessageBuffer::CarriageReturn(ref msg) => {
+                                    ts.override_last(chat, msg.clone())
+                                }
+                            }
+
+                            // telegram seems to store the end of the request as time
+                            // if timed before sending one gets a lot of timeouts
+                            ts.last_send_times[chat] = time::precise_time_ns();
                         }
 
-                        // telegram seems to store the end of the request as time
-                        // if timed before sending one gets a lot of timeouts
-                        ts.last_send_time = time::precise_time_ns();
+                        if ts.state.all_failed() {
+                            ts.all_failed.store(true, Ordering::SeqCst);
+                        }
                     }
                 }
             }
         }
     }
 
-    fn combine_messages(message_buffer: &mut Arc<Mutex<VecDeque<MessageBuffer>>>) -> MessageBuffer {
-
-        let mut message_buffer = message_buffer.lock().unwrap();
-        let to_send = message_buffer.pop_front().unwrap();
-
-        match to_send {
-            MessageBuffer::Newline(msg) => {
-                let mut message = msg;
-                let mut message_length = message.chars().count();
-                while message_buffer.len() > 0 {
-
-                    let new_pop = {
-                        message_buffer.pop_front().unwrap()
-                    };
-
-                    if let MessageBuffer::Newline(msg) = new_pop {
-                        // count the chars and not just String.len()
-                        // as the limit is at 4096 utf8 chars defined by
-                        // the telegram api and not 4096 bytes which would be
-                        // String.len() >= 4096
-
-                        let this_message_length = msg.chars().count();
+    // sends only every second to the same chat;
+    // this sleeps until the given chat may receive the next message
+    fn wait_for_rate_limit(&self, chat: usize) {
+        let last_send_time = match self.last_send_times.get(chat) {
+            Some(t) => *t,
+            None => return,
+        };
 
-                        if this_message_length + message_length + 1 >= 4096 {
-                            message_buffer.push_front(MessageBuffer::Newline(msg));
-                            break;
-                        } else {
-                            message.push('\n');
-                            message.push_str(&msg);
-                            message_length += this_message_length + 1;
-                        }
-                    }
-                }
+        let time_diff = time::precise_time_ns() - last_send_time;
 
-                return MessageBuffer::Newline(message);
-            }
-            MessageBuffer::CarriageReturn(msg) => return MessageBuffer::CarriageReturn(msg),
+        if time_diff <= SEND_INTERVAL_NS && last_send_time != 0 {
+            thread::sleep(::std::time::Duration::new(0, (SEND_INTERVAL_NS - time_diff) as u32));
         }
     }
 
-
     // sends the given string if the message is longer than 0
     // if successfully sent, this returns a message id
-    fn send(&mut self, s: String) {
+    fn send(&mut self, chat: usize, s: String) {
         if s.len() > 0 {
-            match self.api.send_message(self.user_id, s, None, None, None, None) {
-                Ok(o) => self.last_sent_message = Some(o),
-                Err(err) => print!("error while sending: {}", err),
+            let mut result = self.api.send_text(self.state.chats[chat].recipient,
+                                                &s,
+                                                &self.options);
+
+            // if the group was upgraded to a supergroup, send there from now on
+            if let Some(to) = migrated_to(&result) {
+                self.migrate(chat, to);
+                result = self.api.send_text(self.state.chats[chat].recipient, &s, &self.options);
             }
+
+            match result {
+                Ok(o) => self.state.sent(chat, o),
+                Err(err) => self.state.failed(chat, err, "sending"),
+            }
+        }
+    }
+
+    // follows the migration and tells the hook at once,
+    // so the new id is not lost if teleecho is killed
+    fn migrate(&mut self, chat: usize, to: i64) {
+        let migration = self.state.migrate(chat, to);
+        if let Some(ref mut hook) = *self.migration_hook.lock().unwrap() {
+            hook(migration);
         }
     }
 
     // overrides the last message with the given string if the message is longer than 0
     // also the id of the last sent message
     // if this id is None, then nothing is done
-    fn override_last(&mut self, s: String) {
+    fn override_last(&mut self, chat: usize, s: String) {
         if s.len() > 0 {
-            match self.last_sent_message.take() {
+            match self.state.chats[chat].last_sent_message.take() {
                 Some(m) => {
 
                     // if trying to override last, but last is the same
                     // ignore this one
-                    let mut is_same_message = false;
-                    if let &telegram_bot::types::MessageType::Text(ref t) = &m.msg {
-                        if t == &s {
-                            is_same_message = true;
-                        }
-                    }
-
-                    if is_same_message {
-                        self.last_sent_message = Some(m);
+                    if m.text == s {
+                        self.state.chats[chat].last_sent_message = Some(m);
                         return;
                     }
 
-                    // get the old text that was sent
-                    let old_text = if let &telegram_bot::types::MessageType::Text(ref t) = &m.msg {
-                        t.clone()
-                    } else {
-                        String::new()
-                    };
+                    let final_message = replace_last_line(&m.text, &s);
 
-                    // split it by newlines
-                    let mut parts = old_text.split("\n").collect::<Vec<&str>>();
+                    // and go; edited messages stay in their topic
+                    let result = self.api.edit_text(m.chat_id,
+                                                    m.message_id,
+                                                    &final_message,
+                                                    &self.options);
 
-                    // new when override last is called, the last \n part should be overriden
-                    // so remove this
-                    if parts.len() > 0 {
-                        parts.pop();
+                    // the message is gone with the old group, so it is
+                    // sent again to the supergroup
+                    if let Some(to) = migrated_to(&result) {
+                        self.migrate(chat, to);
+                        self.send(chat, final_message);
+                        return;
                     }
 
-                    // and push the new message there
-                    parts.push(&s);
-
-                    // glue everything back together
-                    let final_message = parts.join("\n");
-
-                    // and go
-                    match self.api.edit_message_text(Some(m.chat.id()),
-                                                     Some(m.message_id),
-                                                     None,
-                                                     final_message,
-                                                     None,
-                                                     None,
-                                                     None) {
-                        Ok(o) => self.last_sent_message = Some(o),
+                    match result {
+                        Ok(o) => self.state.sent(chat, o),
                         Err(err) => {
-                            self.last_sent_message = Some(m);
-                            println!("error while overriding {}", err);
+                            self.state.chats[chat].last_sent_message = Some(m);
+                            self.state.failed(chat, err, "overriding");
                         }
                     }
                 }
-                None => println!("None message was given"),
+                // nothing was sent to this chat yet, so there is nothing to replace
+                None => {}
+            }
+        }
+    }
+}
+
+/// builds a `TeleechoProcessor`, e.g. from a connection of the config file:
+///
+/// ```no_run
+/// # use teleecho::{Config, TeleechoBuilder};
+/// let config = Config::load(&Config::default_path().unwrap()).unwrap();
+/// let connection = config.find(Some("backupbot")).unwrap();
+/// let processor = TeleechoBuilder::from_connection(connection).unwrap().silent(true).build();
+/// ```
+#[derive(Debug, Clone)]
+pub struct TeleechoBuilder {
+    token: String,
+    recipients: Vec<Recipient>,
+    topic: Option<i64>,
+    options: SendOptions,
+}
+
+impl TeleechoBuilder {
+    /// a builder for the bot with the given token, without any chats yet
+    pub fn new(token: &str) -> TeleechoBuilder {
+        TeleechoBuilder {
+            token: String::from(token),
+            recipients: vec![],
+            topic: None,
+            options: SendOptions::default(),
+        }
+    }
+
+    /// a builder with the token, chats and options of the given connection;
+    /// fails if the token can not be resolved
+    pub fn from_connection(connection: &Connection) -> Result<TeleechoBuilder> {
+        Ok(TeleechoBuilder {
+            token: try!(connection.resolve_token()),
+            recipients: connection.recipients(None),
+            topic: None,
+            options: connection.send_options(),
+        })
+    }
+
+    /// also sends to the given chat
+    pub fn chat(mut self, chat_id: i64) -> TeleechoBuilder {
+        self.recipients.push(Recipient::chat(chat_id));
+        self
+    }
+
+    /// also sends to the given chat and topic
+    pub fn recipient(mut self, recipient: Recipient) -> TeleechoBuilder {
+        self.recipients.push(recipient);
+        self
+    }
+
+    /// sends into the given forum topic in all chats that can have topics, also in
+    /// chats added later. the type of the chats is not known, so these are all
+    /// chats with a negative id, groups and channels
+    pub fn topic(mut self, thread_id: i64) -> TeleechoBuilder {
+        self.topic = Some(thread_id);
+        self
+    }
+
+    /// replaces all options
+    pub fn options(mut self, options: SendOptions) -> TeleechoBuilder {
+        self.options = options;
+        self
+    }
+
+    /// `Markdown` or `HTML`; None for plain text
+    pub fn parse_mode(mut self, parse_mode: Option<&str>) -> TeleechoBuilder {
+        self.options.parse_mode = parse_mode.map(String::from);
+        self
+    }
+
+    /// if set, messages arrive without a notification sound
+    pub fn silent(mut self, silent: bool) -> TeleechoBuilder {
+        self.options.silent = silent;
+        self
+    }
+
+    /// if set, no previews for links are shown
+    pub fn disable_web_page_preview(mut self, disable: bool) -> TeleechoBuilder {
+        self.options.disable_web_page_preview = disable;
+        self
+    }
+
+    /// starts the send thread; fails if the token is malformed
+    pub fn build(mut self) -> Result<TeleechoProcessor> {
+        if let Some(topic) = self.topic {
+            for r in self.recipients.iter_mut().filter(|r| r.chat_id < 0) {
+                r.thread_id = Some(topic);
             }
         }
+        TeleechoProcessor::create_with_options(&self.token, &self.recipients, self.options)
     }
 }
 
+/// forwards text to telegram chats. the text is split into lines, which are
+/// sent by a background thread; lines arriving faster than telegram allows
+/// are combined into one message, and a line starting with a carriage return
+/// replaces the last line, like progress bars do in a terminal.
+///
+/// text is given char by char with `append_to_input_buffer`, or as bytes
+/// through `std::io::Write`, e.g. with `write!`
 pub struct TeleechoProcessor {
     /// this is the input buffer
     /// this is different from the message buffer, as messages are the 
@@ -243,41 +650,140 @@ pub struct TeleechoProcessor {
     /// keep account of how long the input buffer is
     input_buffer_size: usize,
 
+    /// bytes written that do not form a complete utf8 char yet
+    pending_bytes: Vec<u8>,
+
     sender: Sender<BufferChangeEvent>,
 
     /// a buffer that stores the messages to be sent
     message_buffer: Arc<Mutex<VecDeque<MessageBuffer>>>,
 
-    handle: Option<JoinHandle<()>>,
+    handle: Option<JoinHandle<DeliveryReport>>,
+
+    /// what happened to the messages until the last `wait_delivered` or `close`
+    report: DeliveryReport,
+
+    /// set by the sender once no chat can be sent to anymore
+    all_failed: Arc<AtomicBool>,
+
+    /// shared with the send thread, see `on_migration`
+    migration_hook: Arc<Mutex<Option<MigrationHook>>>,
+
+    /// the number of messages added to the message buffer so far,
+    /// and the number the sender took from it
+    queued: usize,
+    taken: Arc<AtomicUsize>,
 }
 
 impl TeleechoProcessor {
     pub fn create(token: &str, user_id: i64) -> Result<TeleechoProcessor> {
+        TeleechoProcessor::create_with_options(token,
+                                               &[Recipient::chat(user_id)],
+                                               SendOptions::default())
+    }
 
-        let api = try!(telegram_bot::Api::from_token(&token));
+    /// like `create` but sends every message to all given recipients
+    /// with the given options
+    pub fn create_with_options(token: &str,
+                               recipients: &[Recipient],
+                               options: SendOptions)
+                               -> Result<TeleechoProcessor> {
 
-        let (sender, handle, buffer) = TeleechoSender::create(api, user_id);
+        let api = try!(BotApi::from_token(token));
+
+        let migration_hook = Arc::new(Mutex::new(None));
+        let taken = Arc::new(AtomicUsize::new(0));
+        let (sender, handle, buffer, all_failed) = TeleechoSender::create(api,
+                                                                          token,
+                                                                          recipients,
+                                                                          options,
+                                                                          migration_hook.clone(),
+                                                                          taken.clone());
 
         Ok(TeleechoProcessor {
             input_buffer: String::with_capacity(8000),
             input_buffer_size: 0,
+            pending_bytes: vec![],
             sender: sender,
             message_buffer: buffer.clone(),
             handle: Some(handle),
+            report: DeliveryReport::default(),
+            all_failed: all_failed,
+            migration_hook: migration_hook,
+            queued: 0,
+            taken: taken,
         })
     }
 
-    /// if the send thread is still running this sends the kill signal 
-    /// and waits for the thread to finish up
-    /// if was already closed, nothing will be done
-    pub fn close(&mut self) {
+    /// a builder for a processor sending with the bot with the given token
+    pub fn builder(token: &str) -> TeleechoBuilder {
+        TeleechoBuilder::new(token)
+    }
+
+    /// sends the incomplete line, if any, and waits until all messages are sent;
+    /// returns what happened to all messages so far
+    pub fn wait_delivered(&mut self) -> &DeliveryReport {
+        let report = self.request_report().recv().unwrap_or(DeliveryReport::default());
+        self.add_report(report)
+    }
+
+    /// `wait_delivered` in two steps, so a shared processor need not be locked
+    /// while waiting: this sends the incomplete line and returns where the report
+    /// arrives once all messages are sent, which is then given to `add_report`
+    pub fn request_report(&mut self) -> Receiver<DeliveryReport> {
+        let (reply, report) = mpsc::channel();
+        if self.handle.is_some() {
+            self.flush_input_buffer();
+            self.sender.send(BufferChangeEvent::Flush(reply)).unwrap();
+        } else {
+            let _ = reply.send(DeliveryReport::default());
+        }
+        report
+    }
+
+    /// adds a report received after `request_report`;
+    /// returns what happened to all messages so far
+    pub fn add_report(&mut self, report: DeliveryReport) -> &DeliveryReport {
+        self.report.merge(report);
+        &self.report
+    }
+
+    /// if the send thread is still running this sends the incomplete line,
+    /// sends the kill signal and waits for the thread to finish up
+    /// if was already closed, nothing will be done.
+    /// returns what happened to all messages
+    pub fn close(&mut self) -> &DeliveryReport {
+        if self.handle.is_some() {
+            self.finish_pending_bytes();
+            self.flush_input_buffer();
+        }
         match self.handle.take() {
             Some(handle) => {
                 self.sender.send(BufferChangeEvent::Kill).unwrap();
-                handle.join().unwrap();
+                let report = handle.join().unwrap();
+                self.report.merge(report);
             }
             None => {}
         }
+        &self.report
+    }
+
+    /// what happened to the messages until the last `wait_delivered` or `close`
+    pub fn report(&self) -> &DeliveryReport {
+        &self.report
+    }
+
+    /// calls the given function from the send thread whenever a group turns out
+    /// to be upgraded to a supergroup, before the next message is sent there
+    pub fn on_migration<F>(&mut self, hook: F)
+        where F: FnMut(ChatMigration) + Send + 'static
+    {
+        *self.migration_hook.lock().unwrap() = Some(Box::new(hook));
+    }
+
+    /// whether every chat failed fatally, so further input would be dropped
+    pub fn has_failed(&self) -> bool {
+        self.all_failed.load(Ordering::SeqCst)
     }
 
     /// given a MessageBuffer event this appends the message
@@ -285,23 +791,13 @@ impl TeleechoProcessor {
     /// if CarriageReturn and another message present this
     /// message is overriden
     fn append_to_send_buffer(&mut self, msg: MessageBuffer) {
+        {
+            let mut buffer = self.message_buffer.lock().unwrap();
+            let queued = buffer.len();
+            queue_message(&mut buffer, msg);
 
-        let mut msg_buffer = self.message_buffer.lock().unwrap();
-
-        if msg_buffer.len() == 0 {
-            msg_buffer.push_back(msg);
-        } else if let &MessageBuffer::Newline(_) = &msg {
-            msg_buffer.push_back(msg);
-        } else if let MessageBuffer::CarriageReturn(s) = msg {
-            // get last element; will exist, as len() > 0
-            let last_elem = msg_buffer.pop_back().unwrap();
-
-            let new_elem = match last_elem {
-                MessageBuffer::CarriageReturn(_) => MessageBuffer::CarriageReturn(s),
-                MessageBuffer::Newline(_) => MessageBuffer::Newline(s),
-            };
-
-            msg_buffer.push_back(new_elem);
+            // a replaced line is no new message
+            self.queued += buffer.len() - queued;
         }
 
         self.sender.send(BufferChangeEvent::NewElement).unwrap();
@@ -329,6 +825,39 @@ impl TeleechoProcessor {
         }
     }
 
+    /// the number of messages waiting to be sent
+    pub fn queued_messages(&self) -> usize {
+        self.message_buffer.lock().unwrap().len()
+    }
+
+    /// marks the messages queued so far; see `has_left_queue`
+    pub fn queue_mark(&self) -> usize {
+        self.queued
+    }
+
+    /// whether the send thread took all messages that were queued when
+    /// `queue_mark` returned the given mark, so they are sent or being sent
+    pub fn has_left_queue(&self, mark: usize) -> bool {
+        self.taken.load(Ordering::SeqCst) >= mark
+    }
+
+    /// sends the input received so far, even if the line is not complete yet;
+    /// e.g. for prompts that wait for an answer on the same line
+    pub fn flush_input_buffer(&mut self) {
+        if self.input_buffer_size > 0 {
+            self.convert_to_message();
+        }
+    }
+
+    /// the rest of a char cut off by the last write will not come anymore,
+    /// so it is replaced like invalid utf8
+    fn finish_pending_bytes(&mut self) {
+        if !self.pending_bytes.is_empty() {
+            self.pending_bytes.clear();
+            self.append_to_input_buffer('\u{fffd}');
+        }
+    }
+
     /// call this when '\r', '\n' or 4096 chars are reached
     /// this then converts this to a message
     /// and appends this to the input buffer
@@ -365,6 +894,56 @@ impl TeleechoProcessor {
     }
 }
 
+/// whether the given bytes are the start of a utf8 char that is not complete yet
+fn incomplete_utf8(bytes: &[u8]) -> bool {
+    let expected = match bytes.first() {
+        Some(&b) if b & 0xe0 == 0xc0 => 2,
+        Some(&b) if b & 0xf0 == 0xe0 => 3,
+        Some(&b) if b & 0xf8 == 0xf0 => 4,
+        _ => return false,
+    };
+    bytes.len() < expected && bytes[1..].iter().all(|b| b & 0xc0 == 0x80)
+}
+
+/// feeds the written text into the processor; invalid utf8 is replaced.
+/// `flush` sends the incomplete line, but unlike `TeleechoProcessor::wait_delivered`
+/// does not wait for the messages to be sent
+impl Write for TeleechoProcessor {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut bytes = mem::replace(&mut self.pending_bytes, vec![]);
+        bytes.extend_from_slice(buf);
+
+        let mut start = 0;
+        while start < bytes.len() {
+            let valid = match str::from_utf8(&bytes[start..]) {
+                Ok(_) => bytes.len(),
+                Err(e) => start + e.valid_up_to(),
+            };
+            for c in str::from_utf8(&bytes[start..valid]).unwrap().chars() {
+                self.append_to_input_buffer(c);
+            }
+
+            if valid == bytes.len() {
+                break;
+            } else if incomplete_utf8(&bytes[valid..]) {
+                // the rest of the char comes with the next write
+                self.pending_bytes = bytes[valid..].to_vec();
+                break;
+            } else {
+                self.append_to_input_buffer('\u{fffd}');
+                start = valid + 1;
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_input_buffer();
+        Ok(())
+    }
+}
+
 // implement drop for the processor to
 // prevent forgetting to call close
 impl Drop for TeleechoProcessor {
@@ -373,72 +952,105 @@ impl Drop for TeleechoProcessor {
     }
 }
 
-/// given a token this starts a listener for telegram messages.
-/// if the randomly generated pairing number is send via telegram
-/// to this bot a new connection pair is returned
-/// if something goes wrong an Error is returned
-pub fn register_connection(token: &str) -> Result<(String, i64)> {
-
-    let api = try!(telegram_bot::Api::from_token(&token));
-    let me = try!(api.get_me());
-
-    // generate a random number to be used for pairing
-    // its probably possible to just use the "/start" command
-    let mut rng = rand::thread_rng();
-    let random_number = rng.gen_range(0, 99999);
-
-    println!("send the following number to the {} bot:\t{}",
-             me.username.unwrap(),
-             random_number);
-
-    // this will hold the user id to send the messages to
-    let mut user_id = None;
-
-    // create the listener and listen what the user has to say
-    let mut listener = api.listener(telegram_bot::ListeningMethod::LongPoll(None));
-    try!(listener.listen(|u| {
-        // If the received update contains a message...
-        if let Some(m) = u.message {
-            let name = m.from.first_name;
-
-            // Match message type
-            match m.msg {
-                telegram_bot::MessageType::Text(t) => {
-
-                    // if the corret number was specified
-                    if t == format!("{}", random_number) {
-
-                        // notify the user
-                        // but dont panic if this did not work
-                        match api.send_message(m.chat.id(),
-                                               String::from("correct number!"),
-                                               None,
-                                               None,
-                                               None,
-                                               None) {
-                            Ok(_) => {}
-                            Err(err) => println!("Error while register {}", err),
-                        };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::vec_deque::VecDeque;
+    use std::io::Write;
+
+    // a processor without chats, so nothing is sent anywhere
+    fn processor() -> TeleechoProcessor {
+        TeleechoProcessor::create_with_options("123456:ABC-DEF1234ghIkl",
+                                               &[],
+                                               SendOptions::default())
+            .unwrap()
+    }
+
+    fn newline(s: &str) -> MessageBuffer {
+        MessageBuffer::Newline(String::from(s))
+    }
 
-                        user_id = Some(m.chat.id());
-                        return Ok(telegram_bot::ListeningAction::Stop);
+    fn carriage_return(s: &str) -> MessageBuffer {
+        MessageBuffer::CarriageReturn(String::from(s))
+    }
 
-                    } else {
-                        println!("received wrong number from {}", name);
-                    }
-                }
-                _ => {}
-            }
-        }
+    #[test]
+    fn incomplete_utf8_chars() {
+        assert!(incomplete_utf8(&[0xc3]));
+        assert!(incomplete_utf8(&[0xe2, 0x82]));
+        assert!(incomplete_utf8(&[0xf0, 0x9f, 0x98]));
+        assert!(!incomplete_utf8(&[0xe2, 0x82, 0xac]));
+        assert!(!incomplete_utf8(&[0xc3, 0x41]));
+        assert!(!incomplete_utf8(&[0xff]));
+        assert!(!incomplete_utf8(&[]));
+    }
+
+    #[test]
+    fn write_joins_chars_split_across_writes() {
+        let mut p = processor();
+        p.write(&[b'a', 0xe2, 0x82]).unwrap();
+        assert_eq!(p.input_buffer, "a");
+        p.write(&[0xac, b'b']).unwrap();
+        assert_eq!(p.input_buffer, "a\u{20ac}b");
+        assert!(p.pending_bytes.is_empty());
+    }
+
+    #[test]
+    fn write_replaces_invalid_utf8() {
+        let mut p = processor();
+        p.write(&[b'a', 0xff, b'b', 0xc3, b'c']).unwrap();
+        assert_eq!(p.input_buffer, "a\u{fffd}b\u{fffd}c");
+    }
+
+    #[test]
+    fn cut_off_char_is_replaced_when_finished() {
+        let mut p = processor();
+        p.write(&[b'a', 0xc3]).unwrap();
+        p.finish_pending_bytes();
+        assert_eq!(p.input_buffer, "a\u{fffd}");
+        assert!(p.pending_bytes.is_empty());
+    }
+
+    #[test]
+    fn combine_joins_lines() {
+        let mut queue = VecDeque::new();
+        queue.push_back(newline("a"));
+        queue.push_back(newline("b"));
+        assert_eq!(combine_messages(&mut queue), newline("a\nb"));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn combine_stops_at_message_length() {
+        let long = ::std::iter::repeat('x').take(4000).collect::<String>();
+        let mut queue = VecDeque::new();
+        queue.push_back(newline(&long));
+        queue.push_back(newline(&long[..100]));
+        assert_eq!(combine_messages(&mut queue), newline(&long));
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn combine_sends_carriage_return_alone() {
+        let mut queue = VecDeque::new();
+        queue.push_back(carriage_return("a"));
+        queue.push_back(newline("b"));
+        assert_eq!(combine_messages(&mut queue), carriage_return("a"));
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn queue_replaces_the_last_line() {
+        let mut queue = VecDeque::new();
+        queue_message(&mut queue, carriage_return("a"));
+        assert_eq!(queue, vec![carriage_return("a")].into_iter().collect::<VecDeque<_>>());
 
-        // If none of the "try!" statements returned an error: It's Ok!
-        Ok(telegram_bot::ListeningAction::Continue)
-    }));
+        queue_message(&mut queue, carriage_return("b"));
+        assert_eq!(queue, vec![carriage_return("b")].into_iter().collect::<VecDeque<_>>());
 
-    // the user id must have been found, otherwise the connection is not complete
-    if user_id.is_none() {
-        Err("user id is empty".into())
-    } else {
-        Ok((String::from(token), user_id.unwrap()))
+        queue_message(&mut queue, newline("c"));
+        queue_message(&mut queue, carriage_return("d"));
+        assert_eq!(queue,
+                   vec![carriage_return("b"), newline("d")].into_iter().collect::<VecDeque<_>>());
     }
 }
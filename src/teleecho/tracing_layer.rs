@@ -0,0 +1,210 @@
+extern crate tracing;
+extern crate tracing_subscriber;
+
+use self::tracing::{Event, Id, Level, Subscriber};
+use self::tracing::field::{Field, Visit};
+use self::tracing::span::{Attributes, Record};
+use self::tracing_subscriber::layer::{Context, Layer};
+use self::tracing_subscriber::registry::LookupSpan;
+use std::fmt;
+use std::sync::Mutex;
+use std::thread;
+use teleecho::logger::{RecordQueue, TeleechoLoggerHandle};
+use teleecho::teleecho::{SENDER_THREAD_NAME, TeleechoProcessor};
+
+/// collects the fields of a span or event as `name=value`;
+/// the message of an event is kept separately
+#[derive(Default)]
+struct FieldVisitor {
+    message: Option<String>,
+    fields: Vec<String>,
+}
+
+impl Visit for FieldVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = Some(String::from(value));
+        } else {
+            self.fields.push(format!("{}={}", field.name(), value));
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &fmt::Debug) {
+        if field.name() == "message" {
+            self.message = Some(format!("{:?}", value));
+        } else {
+            self.fields.push(format!("{}={:?}", field.name(), value));
+        }
+    }
+}
+
+/// the formatted fields of a span, stored in its extensions
+struct SpanFields(Vec<String>);
+
+/// the last event sent and how often it was repeated since
+struct LastEvent {
+    text: String,
+
+    /// the queue mark after the event, to know whether it still waits
+    mark: usize,
+    repeated: usize,
+}
+
+/// a `tracing_subscriber` layer that sends events with a `TeleechoProcessor`:
+///
+/// ```no_run
+/// extern crate teleecho;
+/// extern crate tracing;
+/// extern crate tracing_subscriber;
+///
+/// use tracing_subscriber::prelude::*;
+///
+/// # fn main() {
+/// let processor = teleecho::TeleechoProcessor::builder("123456:ABC-DEF1234ghIkl")
+///     .chat(12345678)
+///     .build()
+///     .unwrap();
+/// let layer = teleecho::TeleechoLayer::new(processor).level(tracing::Level::WARN);
+/// let handle = layer.handle();
+/// tracing_subscriber::registry().with(layer).init();
+///
+/// // before exiting, so queued events are not lost
+/// handle.flush();
+/// # }
+/// ```
+///
+/// every event is sent as the names and fields of its spans as header line,
+/// followed by level, message and fields of the event.
+/// an event identical to the last one is not sent again while the last one
+/// still waits in the queue; the number of repetitions is sent with the next event.
+/// like `TeleechoLogger` this never waits for telegram and drops events instead
+pub struct TeleechoLayer {
+    queue: RecordQueue,
+    level: Level,
+    last: Mutex<Option<LastEvent>>,
+}
+
+/// gives access to the processor after the layer was installed
+pub type TeleechoLayerHandle = TeleechoLoggerHandle;
+
+impl TeleechoLayer {
+    /// a layer sending warnings and errors with the given processor
+    pub fn new(processor: TeleechoProcessor) -> TeleechoLayer {
+        TeleechoLayer {
+            queue: RecordQueue::new(processor, "events"),
+            level: Level::WARN,
+            last: Mutex::new(None),
+        }
+    }
+
+    /// the minimum level of events that are sent
+    pub fn level(mut self, level: Level) -> TeleechoLayer {
+        self.level = level;
+        self
+    }
+
+    /// events are dropped while this many messages wait to be sent
+    pub fn queue_capacity(mut self, capacity: usize) -> TeleechoLayer {
+        self.queue.set_capacity(capacity);
+        self
+    }
+
+    /// a handle to flush the processor once the layer is installed
+    pub fn handle(&self) -> TeleechoLayerHandle {
+        self.queue.handle()
+    }
+
+    // sends the given event unless it repeats the last one that was not sent yet
+    fn send(&self, header: Option<String>, body: String) {
+        let text = match header {
+            Some(header) => format!("{}\n{}", header, body),
+            None => body,
+        };
+
+        let mut last = self.last.lock().unwrap();
+        let mark = self.queue.push(|processor| {
+            let mut message = String::new();
+            if let Some(ref mut last) = *last {
+                if last.text == text && !processor.has_left_queue(last.mark) {
+                    last.repeated += 1;
+                    return None;
+                }
+                if last.repeated > 0 {
+                    message.push_str(&format!("(last event repeated {} times)\n",
+                                              last.repeated));
+                }
+            }
+            message.push_str(&text);
+            Some(message)
+        });
+
+        if let Some(mark) = mark {
+            *last = Some(LastEvent {
+                text: text,
+                mark: mark,
+                repeated: 0,
+            });
+        }
+    }
+}
+
+impl<S> Layer<S> for TeleechoLayer
+    where S: Subscriber + for<'a> LookupSpan<'a>
+{
+    fn on_new_span(&self, attrs: &Attributes, id: &Id, ctx: Context<S>) {
+        if let Some(span) = ctx.span(id) {
+            let mut visitor = FieldVisitor::default();
+            attrs.record(&mut visitor);
+            span.extensions_mut().insert(SpanFields(visitor.fields));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record, ctx: Context<S>) {
+        if let Some(span) = ctx.span(id) {
+            let mut visitor = FieldVisitor::default();
+            values.record(&mut visitor);
+            if let Some(fields) = span.extensions_mut().get_mut::<SpanFields>() {
+                fields.0.extend(visitor.fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event, ctx: Context<S>) {
+        // more verbose levels are greater
+        if *event.metadata().level() > self.level ||
+           thread::current().name() == Some(SENDER_THREAD_NAME) {
+            return;
+        }
+
+        let header = ctx.event_scope(event).map(|scope| {
+            scope.from_root()
+                .map(|span| {
+                    let extensions = span.extensions();
+                    match extensions.get::<SpanFields>() {
+                        Some(fields) if !fields.0.is_empty() => {
+                            format!("{}{{{}}}", span.name(), fields.0.join(" "))
+                        }
+                        _ => String::from(span.name()),
+                    }
+                })
+                .collect::<Vec<String>>()
+                .join(" > ")
+        });
+
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+
+        let mut body = format!("{}", event.metadata().level());
+        if let Some(message) = visitor.message {
+            body.push(' ');
+            body.push_str(&message);
+        }
+        for field in visitor.fields {
+            body.push(' ');
+            body.push_str(&field);
+        }
+
+        // a carriage return would replace the last line
+        self.send(header.map(|h| h.replace('\r', "")), body.replace('\r', ""));
+    }
+}
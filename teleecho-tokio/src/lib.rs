@@ -0,0 +1,275 @@
+//! an async api for teleecho on the tokio runtime.
+//!
+//! `AsyncTeleecho` sends with the same semantics as the blocking
+//! `TeleechoProcessor`: at most one message per second in every chat,
+//! lines arriving meanwhile are combined into one message, and a line
+//! starting with a carriage return replaces the last line.
+//! nothing runs on its own thread; `spawn` runs the send loop as task
+//! on the runtime of the caller and returns a sink for the lines:
+//!
+//! ```no_run
+//! use futures::SinkExt;
+//! use teleecho_tokio::{AsyncTeleecho, Line, Recipient, SendOptions};
+//!
+//! # async fn run() -> teleecho::Result<()> {
+//! let teleecho = AsyncTeleecho::new("123456:ABC-DEF1234ghIkl",
+//!                                   &[Recipient::chat(12345678)],
+//!                                   SendOptions::default())?;
+//! let (mut sink, handle) = teleecho.spawn(1000);
+//!
+//! sink.send(Line::Newline(String::from("backup started"))).await.ok();
+//! sink.send(Line::CarriageReturn(String::from("50%"))).await.ok();
+//!
+//! // closing the sink ends the task once everything is sent
+//! sink.close().await.ok();
+//! let report = handle.await.unwrap();
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use serde_json::Value;
+use teleecho::botapi::{self, SentMessage};
+use teleecho::error::{redact, Error, ErrorKind, Result};
+use teleecho::teleecho::{combine_messages, migrated_to, queue_message, replace_last_line,
+                         SendState, SEND_INTERVAL_NS};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{self, Instant};
+use tokio_util::sync::PollSender;
+
+pub use teleecho::teleecho::{ChatMigration, DeliveryFailure, DeliveryReport,
+                             MessageBuffer as Line, Recipient, SendOptions};
+
+/// the lines given to the send loop started with `AsyncTeleecho::spawn`
+pub type LineSink = PollSender<Line>;
+
+/// direct async access to the telegram bot api, like the blocking `BotApi`
+pub struct AsyncBotApi {
+    token: String,
+    client: reqwest::Client,
+}
+
+impl AsyncBotApi {
+    /// fails if the token is obviously malformed
+    pub fn from_token(token: &str) -> Result<AsyncBotApi> {
+        botapi::check_token_format(token)?;
+        Ok(AsyncBotApi {
+            token: String::from(token),
+            client: reqwest::Client::new(),
+        })
+    }
+
+    /// calls the given api method with the given json parameters and returns
+    /// the result field of the answer
+    pub async fn call(&self, method: &str, params: &Value) -> Result<Value> {
+        // the url contains the token, so it must not end up in any error
+        let url = format!("https://api.telegram.org/bot{}/{}", self.token, method);
+        let body = serde_json::to_string(params)?;
+
+        let response = self.client
+            .post(&url)
+            .header("Content-Type", "application/json")
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| self.network_error(e))?;
+        let content = response.text().await.map_err(|e| self.network_error(e))?;
+
+        botapi::parse_answer(&content)
+    }
+
+    /// sends a text message to the given recipient with the given options
+    pub async fn send(&self,
+                      recipient: Recipient,
+                      text: &str,
+                      options: &SendOptions)
+                      -> Result<SentMessage> {
+        let params = botapi::send_text_params(recipient, text, options);
+        SentMessage::from_value(&self.call("sendMessage", &params).await?)
+    }
+
+    /// replaces the text of the given message
+    pub async fn edit(&self,
+                      message: &SentMessage,
+                      text: &str,
+                      options: &SendOptions)
+                      -> Result<SentMessage> {
+        let params = botapi::edit_text_params(message.chat_id, message.message_id, text, options);
+        SentMessage::from_value(&self.call("editMessageText", &params).await?)
+    }
+
+    fn network_error(&self, err: reqwest::Error) -> Error {
+        redact(ErrorKind::Network(err.to_string()).into(), &self.token)
+    }
+}
+
+/// sends to several chats like the blocking sender, but on the tokio runtime
+pub struct AsyncTeleecho {
+    api: AsyncBotApi,
+
+    /// the chats and what happened so far, kept like the blocking sender does
+    state: SendState,
+
+    /// when each chat may receive the next message
+    next_send: Vec<Option<Instant>>,
+    options: SendOptions,
+}
+
+impl AsyncTeleecho {
+    /// sends every message to all given recipients with the given options;
+    /// fails if the token is malformed
+    pub fn new(token: &str,
+               recipients: &[Recipient],
+               options: SendOptions)
+               -> Result<AsyncTeleecho> {
+        Ok(AsyncTeleecho {
+            api: AsyncBotApi::from_token(token)?,
+            state: SendState::new(token, recipients),
+            next_send: vec![None; recipients.len()],
+            options: options,
+        })
+    }
+
+    /// sends the text as new message to every chat, waiting for the rate limit
+    /// of each chat; returns the sent messages. what failed is in the report
+    pub async fn send(&mut self, text: &str) -> Vec<SentMessage> {
+        let mut sent = vec![];
+        if text.len() == 0 {
+            return sent;
+        }
+
+        for chat in 0..self.state.chats.len() {
+            if self.state.chats[chat].failed {
+                self.state.report.failed += 1;
+                continue;
+            }
+            self.wait_for_rate_limit(chat).await;
+
+            if let Some(m) = self.send_to(chat, text).await {
+                sent.push(m);
+            }
+            self.next_send[chat] = Some(Instant::now() + interval());
+        }
+        sent
+    }
+
+    /// replaces the last line of the last message sent to every chat;
+    /// returns the edited messages. what failed is in the report
+    pub async fn edit(&mut self, line: &str) -> Vec<SentMessage> {
+        let mut edited = vec![];
+        if line.len() == 0 {
+            return edited;
+        }
+
+        for chat in 0..self.state.chats.len() {
+            if self.state.chats[chat].failed {
+                self.state.report.failed += 1;
+                continue;
+            }
+            let last = match self.state.chats[chat].last_sent_message.clone() {
+                Some(ref m) if m.text == line => continue,
+                Some(m) => m,
+                None => continue,
+            };
+            self.wait_for_rate_limit(chat).await;
+
+            let text = replace_last_line(&last.text, line);
+            let result = self.api.edit(&last, &text, &self.options).await;
+
+            // the message is gone with the old group, so it is sent again to the supergroup
+            if let Some(to) = migrated_to(&result) {
+                self.state.migrate(chat, to);
+                if let Some(m) = self.send_to(chat, &text).await {
+                    edited.push(m);
+                }
+            } else {
+                match result {
+                    Ok(m) => {
+                        self.state.sent(chat, m.clone());
+                        edited.push(m);
+                    }
+                    Err(err) => self.state.failed(chat, err, "overriding"),
+                }
+            }
+            self.next_send[chat] = Some(Instant::now() + interval());
+        }
+        edited
+    }
+
+    /// what happened to the messages sent so far
+    pub fn report(&self) -> &DeliveryReport {
+        &self.state.report
+    }
+
+    /// runs the send loop as task on the current runtime; the lines given to
+    /// the returned sink are batched and sent like the input of `teleecho`.
+    /// at most `capacity` lines wait in the sink. the task ends once the sink
+    /// is closed and everything is sent, and returns what happened
+    pub fn spawn(self, capacity: usize) -> (LineSink, JoinHandle<DeliveryReport>) {
+        let (sender, receiver) = mpsc::channel(capacity);
+        let handle = tokio::spawn(self.send_loop(receiver));
+        (PollSender::new(sender), handle)
+    }
+
+    async fn send_loop(mut self, mut receiver: mpsc::Receiver<Line>) -> DeliveryReport {
+        let mut queue = VecDeque::new();
+        loop {
+            if queue.is_empty() {
+                match receiver.recv().await {
+                    Some(line) => queue_message(&mut queue, line),
+                    None => return self.state.report,
+                }
+            }
+
+            // wait for the first chat; meanwhile more lines can arrive and be combined
+            self.wait_for_rate_limit(0).await;
+            while let Ok(line) = receiver.try_recv() {
+                queue_message(&mut queue, line);
+            }
+
+            match combine_messages(&mut queue) {
+                Line::Newline(text) => {
+                    self.send(&text).await;
+                }
+                Line::CarriageReturn(line) => {
+                    self.edit(&line).await;
+                }
+            }
+        }
+    }
+
+    async fn wait_for_rate_limit(&self, chat: usize) {
+        if let Some(&Some(next_send)) = self.next_send.get(chat) {
+            time::sleep_until(next_send).await;
+        }
+    }
+
+    // sends to the given chat; a group upgraded to a supergroup is followed
+    async fn send_to(&mut self, chat: usize, text: &str) -> Option<SentMessage> {
+        let recipient = self.state.chats[chat].recipient;
+        let mut result = self.api.send(recipient, text, &self.options).await;
+
+        if let Some(to) = migrated_to(&result) {
+            self.state.migrate(chat, to);
+            result = self.api.send(self.state.chats[chat].recipient, text, &self.options).await;
+        }
+
+        match result {
+            Ok(m) => {
+                self.state.sent(chat, m.clone());
+                Some(m)
+            }
+            Err(err) => {
+                self.state.failed(chat, err, "sending");
+                None
+            }
+        }
+    }
+}
+
+fn interval() -> Duration {
+    Duration::from_nanos(SEND_INTERVAL_NS)
+}